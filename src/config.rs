@@ -0,0 +1,96 @@
+use std::fs::OpenOptions;
+use std::io::Read;
+
+/// User settings.
+// Read from <config dir>/rei/config, one "key value" pair per line. Lines
+// starting with '#' are ignored, as are unknown keys.
+pub struct Config {
+    pub redirect_limit: usize,   // Maximum redirects to follow per request.
+    pub confirm_redirects: bool, // Ask before redirecting to another host or scheme.
+    pub history_redirects: bool, // Add redirecting urls to the history.
+    pub show_urls: bool,         // Print link urls after their labels.
+    pub wrap_width: usize,       // Column to wrap text at, 0 to use the terminal width.
+    pub download_dir: String,    // Directory offered for downloads, empty for the default.
+    pub save_session: bool,      // Save the session on quit and restore it on start.
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            redirect_limit: 5,
+            confirm_redirects: true,
+            history_redirects: false,
+            show_urls: false,
+            wrap_width: 0,
+            download_dir: String::new(),
+            save_session: false,
+        }
+    }
+}
+
+pub fn load() -> Config {
+    let mut config = Config::default();
+    if let Some(mut path) = dirs::config_dir() {
+        path.push("rei");
+        path.push("config");
+        if let Ok(mut file) = OpenOptions::new().read(true).open(path) {
+            let mut buf = String::new();
+            if file.read_to_string(&mut buf).is_err() {
+                return config;
+            }
+            for line in buf.lines() {
+                if line.trim_start().starts_with('#') {
+                    continue;
+                }
+                let mut split = line.split_whitespace();
+                if let (Some(key), Some(value)) = (split.next(), split.next()) {
+                    if !set(&mut config, key, value) {
+                        println!("Ignoring config line: {}", line);
+                    }
+                }
+            }
+        }
+    }
+    config
+}
+
+// Set a single option, returning false if the key or value is not understood.
+fn set(config: &mut Config, key: &str, value: &str) -> bool {
+    match key {
+        "redirect_limit" => match value.parse::<usize>() {
+            Ok(limit) => config.redirect_limit = limit,
+            Err(_) => return false,
+        },
+        "confirm_redirects" => match parse_bool(value) {
+            Some(b) => config.confirm_redirects = b,
+            None => return false,
+        },
+        "history_redirects" => match parse_bool(value) {
+            Some(b) => config.history_redirects = b,
+            None => return false,
+        },
+        "show_urls" => match parse_bool(value) {
+            Some(b) => config.show_urls = b,
+            None => return false,
+        },
+        "wrap_width" => match value.parse::<usize>() {
+            Ok(width) => config.wrap_width = width,
+            Err(_) => return false,
+        },
+        "save_session" => match parse_bool(value) {
+            Some(b) => config.save_session = b,
+            None => return false,
+        },
+        "download_dir" => config.download_dir = value.to_string(),
+        _ => return false,
+    }
+    true
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,78 @@
+use crate::*;
+use std::path::{Path, PathBuf};
+
+/// Saving responses that can not be displayed.
+// The file is named after the last segment of the url and saved to a directory
+// the user picks, defaulting to download_dir from the config or else the
+// system download directory.
+pub fn save(resp: &fetch::Response, config: &config::Config) -> ReiResult<()> {
+    let url = &resp.page.url;
+    let meta = resp.page.header.meta.trim();
+    let name = file_name(url, &mime::Mime::parse(meta));
+    println!("{} ({}, {} bytes)", url, meta, resp.data.len());
+    if !interface::prompt_yes_no(&format!("Save as {}?", name)) {
+        return Err(Error::command("Not saved."));
+    }
+
+    let default_dir = default_dir(config);
+    let dir =
+        match interface::prompt_input(&format!("Directory [{}]", default_dir.display()), false) {
+            Some(dir) => PathBuf::from(dir.trim()),
+            None => default_dir,
+        };
+    if !dir.is_dir() {
+        return Err(Error::Command(format!(
+            "{} is not a directory.",
+            dir.display()
+        )));
+    }
+    let path = dir.join(&name);
+    if path.exists() && !interface::prompt_yes_no(&format!("Overwrite {}?", path.display())) {
+        return Err(Error::command("Not saved."));
+    }
+    write(&path, &resp.data)?;
+    println!("Saved {} bytes to {}", resp.data.len(), path.display());
+    Ok(())
+}
+
+// A safe file name taken from the last segment of the url path. Without one
+// the host is used, with an extension matching the MIME type if it is known.
+fn file_name(url: &url::Url, mime: &mime::Mime) -> String {
+    let segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or("");
+    let name = percent_encoding::percent_decode_str(segment)
+        .decode_utf8_lossy()
+        .replace(&['/', '\\'][..], "_");
+    match name.trim() {
+        "" | "." | ".." => {
+            let base = url.host_str().unwrap_or("download");
+            match mime.extension() {
+                Some(extension) => format!("{}.{}", base, extension),
+                None => base.to_string(),
+            }
+        }
+        name => name.to_string(),
+    }
+}
+
+fn default_dir(config: &config::Config) -> PathBuf {
+    if !config.download_dir.is_empty() {
+        return PathBuf::from(&config.download_dir);
+    }
+    dirs::download_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+fn write(path: &Path, data: &[u8]) -> ReiResult<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| Error::fs("Unable to open", path, e))?;
+    file.write_all(data)
+        .map_err(|e| Error::fs("Unable to write to", path, e))
+}
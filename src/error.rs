@@ -0,0 +1,161 @@
+use gemini_fetch::Status;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Errors surfaced to the user by execute_command.
+#[derive(Debug)]
+pub enum Error {
+    // Resolving, connecting to, or talking to a server failed.
+    Network {
+        context: String,
+        source: Option<std::io::Error>,
+    },
+    // The TLS handshake or certificate handling failed.
+    Tls {
+        context: String,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    // The server answered with a non-success status.
+    Status {
+        status: Status,
+        meta: String,
+    },
+    // The server's response did not follow the protocol.
+    Protocol(String),
+    // A url could not be parsed.
+    Url {
+        input: String,
+        source: url::ParseError,
+    },
+    // Reading or writing a local file failed.
+    Fs {
+        context: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    // A shell command could not be started or talked to.
+    Shell {
+        command: String,
+        source: std::io::Error,
+    },
+    // The config directory, where every store lives, could not be found.
+    NoConfigDir,
+    // A bookmark could not be added, found or loaded.
+    Bookmark(String),
+    // A command could not be carried out as given.
+    Command(String),
+}
+
+pub type ReiResult<T> = Result<T, Error>;
+
+impl Error {
+    pub fn network(context: &str, source: std::io::Error) -> Error {
+        Error::Network {
+            context: context.to_string(),
+            source: Some(source),
+        }
+    }
+
+    pub fn tls<E>(context: &str, source: E) -> Error
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Error::Tls {
+            context: context.to_string(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn url(input: &str, source: url::ParseError) -> Error {
+        Error::Url {
+            input: input.to_string(),
+            source,
+        }
+    }
+
+    pub fn fs(context: &str, path: &std::path::Path, source: std::io::Error) -> Error {
+        Error::Fs {
+            context: context.to_string(),
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn shell(command: &str, source: std::io::Error) -> Error {
+        Error::Shell {
+            command: command.to_string(),
+            source,
+        }
+    }
+
+    pub fn command(msg: &str) -> Error {
+        Error::Command(msg.to_string())
+    }
+
+    pub fn pattern(source: regex::Error) -> Error {
+        Error::Command(format!("Invalid pattern: {}", source))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network { context, source } => match source {
+                Some(source) => write!(f, "{}: {}", context, source),
+                None => write!(f, "{}", context),
+            },
+            Error::Tls { context, source } => match source {
+                Some(source) => write!(f, "{}: {}", context, source),
+                None => write!(f, "{}", context),
+            },
+            Error::Status { status, meta } => {
+                write!(
+                    f,
+                    "{} ({})",
+                    crate::fetch::status_message(status),
+                    crate::fetch::status_code(status)
+                )?;
+                if !meta.trim().is_empty() {
+                    write!(f, ": {}", meta.trim())?;
+                }
+                if *status == Status::ClientCertificateRequired {
+                    write!(
+                        f,
+                        "\nCreate an identity with In <name> and attach it with Ia <name>."
+                    )?;
+                }
+                Ok(())
+            }
+            Error::Protocol(msg) | Error::Bookmark(msg) | Error::Command(msg) => {
+                write!(f, "{}", msg)
+            }
+            Error::Url { input, source } => write!(f, "Unable to parse url {}: {}", input, source),
+            Error::Fs {
+                context,
+                path,
+                source,
+            } => write!(f, "{} {}: {}", context, path.display(), source),
+            Error::Shell { command, source } => write!(f, "Unable to run {}: {}", command, source),
+            Error::NoConfigDir => write!(f, "Unable to find config directory."),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network {
+                source: Some(source),
+                ..
+            }
+            | Error::Fs { source, .. }
+            | Error::Shell { source, .. } => Some(source),
+            Error::Tls {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            Error::Url { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
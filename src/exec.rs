@@ -1,34 +1,47 @@
 use crate::*;
 /// Command Implementations and Helpers
-// Execute the users passed in command.
+// Execute the users passed in command, printing any error it produces.
 // Returns false if the program should terminate.
 pub async fn execute_command(
     cmd: ParseResponse,
     buf: &mut PageBuf,
     hist: &mut History,
     marks: &mut Bookmarks,
+    config: &mut config::Config,
 ) -> bool {
+    if let ParseResponse::Quit = cmd {
+        return false;
+    }
+    if let Err(e) = run_command(cmd, buf, hist, marks, config).await {
+        println!("{}", e);
+    }
+    true
+}
+
+async fn run_command(
+    cmd: ParseResponse,
+    buf: &mut PageBuf,
+    hist: &mut History,
+    marks: &mut Bookmarks,
+    config: &mut config::Config,
+) -> ReiResult<()> {
     match cmd {
         ParseResponse::JumpToLine(line) => {
             let page_len = buf.lines.len();
             if line < page_len {
                 buf.curr_line = line;
-                print_gemtext_line(&buf.lines[buf.curr_line]);
+                print_gemtext_line(&buf.lines[buf.curr_line], None, config);
             } else {
                 println!("?");
-                return true;
             }
         }
-        ParseResponse::GoUrl(url) => match go_url(&url).await {
-            Ok(page) => {
-                if page.body.is_some() {
-                    let _ = load_page(&page, buf, hist, true);
-                    println!("{}", page.body.unwrap().len());
-                }
-                return true;
+        ParseResponse::MarkLine(mark, line) => {
+            if line >= buf.lines.len() {
+                return Err(Error::Command(format!("No line {}.", line + 1)));
             }
-            Err(msg) => println!("{}", msg),
-        },
+            buf.line_marks.insert(mark, line);
+        }
+        ParseResponse::GoUrl(url) => visit(&url, buf, hist, true, config).await?,
         ParseResponse::Print {
             use_range: _,
             start: _,
@@ -39,90 +52,58 @@ pub async fn execute_command(
             start: _,
             stop: _,
         } => {
-            if print_with_args(&cmd, buf).is_ok() {
-                return true;
-            }
+            print_with_args(&cmd, buf, config)?;
         }
         ParseResponse::Page(size) => {
-            let cmd = ParseResponse::Print {
-                use_range: true,
-                start: buf.curr_line,
-                stop: buf.curr_line + size,
-            };
-            if print_with_args(&cmd, buf).is_ok() {
-                return true;
+            // Print whole lines until at least size rows have been shown.
+            let mut rows = 0;
+            let mut i = buf.curr_line;
+            while i < buf.lines.len() && rows < size {
+                rows += print_block_alt(buf, i);
+                rows += print_gemtext_line(&buf.lines[i], None, config);
+                buf.curr_line = i;
+                i += 1;
             }
         }
         ParseResponse::FollowLink(dest_id) => {
-            for line in &buf.lines {
-                if let GemTextLine::Link(id, _, url) = line {
-                    if *id == dest_id {
-                        match go_url(&url).await {
-                            Ok(page) => {
-                                if page.body.is_some() && load_page(&page, buf, hist, true).is_ok()
-                                {
-                                    println!("{}", page.body.unwrap().len());
-                                }
-                                return true;
-                            }
-                            Err(msg) => println!("{}", msg),
-                        }
-                    }
-                }
+            let url = buf.lines.iter().find_map(|line| match line {
+                GemTextLine::Link(id, _, url) if *id == dest_id => Some(url.clone()),
+                _ => None,
+            });
+            match url {
+                Some(url) => visit(&url, buf, hist, true, config).await?,
+                None => return Err(Error::Command(format!("No link {}.", dest_id))),
             }
-            return true;
         }
         ParseResponse::GoBack(mut depth) => {
             if depth < 1 {
                 depth = 1;
             }
-            if hist.entry.is_empty() {
-                return true;
-            }
-            if hist.entry.len() == 1 || hist.curr_entry == 0 {
-                return true;
+            if hist.entry.len() <= 1 || hist.curr_entry == 0 {
+                return Ok(());
             }
             if depth > hist.curr_entry {
                 hist.curr_entry = 0;
             } else {
                 hist.curr_entry -= depth;
             }
-            let url: &url::Url = &hist.entry[hist.curr_entry];
-            match go_url(&url).await {
-                Ok(page) => {
-                    if page.body.is_some() && load_page(&page, buf, hist, false).is_ok() {
-                        println!("{}", page.body.unwrap().len());
-                    }
-                    return true;
-                }
-                Err(msg) => println!("{}", msg),
-            }
+            let url = hist.entry[hist.curr_entry].clone();
+            visit(&url, buf, hist, false, config).await?;
         }
         ParseResponse::GoForward(mut depth) => {
             if depth < 1 {
                 depth = 1;
             }
-            if hist.entry.is_empty() {
-                return true;
-            }
-            if hist.entry.len() == 1 || hist.curr_entry == hist.entry.len() - 1 {
-                return true;
+            if hist.entry.len() <= 1 || hist.curr_entry == hist.entry.len() - 1 {
+                return Ok(());
             }
             if hist.curr_entry + depth >= hist.entry.len() - 1 {
                 hist.curr_entry = hist.entry.len() - 1;
             } else {
                 hist.curr_entry += 1;
             }
-            let url: &url::Url = &hist.entry[hist.curr_entry];
-            match go_url(&url).await {
-                Ok(page) => {
-                    if page.body.is_some() && load_page(&page, buf, hist, false).is_ok() {
-                        println!("{}", page.body.unwrap().len());
-                    }
-                    return true;
-                }
-                Err(msg) => println!("{}", msg),
-            }
+            let url = hist.entry[hist.curr_entry].clone();
+            visit(&url, buf, hist, false, config).await?;
         }
         ParseResponse::History(depth) => {
             if depth <= 0 {
@@ -144,90 +125,405 @@ pub async fn execute_command(
                 }
             }
         }
-        ParseResponse::SearchForwards(re) => {
-            if let Ok(re) = regex::Regex::new(re.as_str()) {
-                for i in buf.curr_line..(buf.lines.len() - 1) {
-                    let text = match &buf.lines[i] {
-                        GemTextLine::H1(text)
-                        | GemTextLine::H2(text)
-                        | GemTextLine::H3(text)
-                        | GemTextLine::Line(text) => text,
-                        GemTextLine::Link(_, text, _) => text,
-                    };
-                    if re.is_match(&text) {
-                        buf.curr_line = i;
-                        print_gemtext_line(&buf.lines[buf.curr_line]);
-                        return true;
-                    }
-                }
+        ParseResponse::SearchForwards(pattern) => search(buf, &pattern, true, config)?,
+        ParseResponse::SearchBackwards(pattern) => search(buf, &pattern, false, config)?,
+        ParseResponse::ListMatches(pattern, scope) => list_matches(buf, pattern, &scope)?,
+        ParseResponse::Global {
+            pattern,
+            invert,
+            command,
+        } => {
+            let re = last_pattern(buf, pattern)?;
+            let matches: Vec<usize> = buf
+                .lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| re.is_match(line.text()) != invert)
+                .map(|(i, _)| i)
+                .collect();
+            if matches.is_empty() {
+                println!("?");
             }
-            println!("?");
-            return true;
-        }
-        ParseResponse::SearchBackwards(re) => {
-            if let Ok(re) = regex::Regex::new(re.as_str()) {
-                for i in 0..buf.curr_line {
-                    let i = buf.curr_line - i;
-                    let text = match &buf.lines[i] {
-                        GemTextLine::H1(text)
-                        | GemTextLine::H2(text)
-                        | GemTextLine::H3(text)
-                        | GemTextLine::Line(text) => text,
-                        GemTextLine::Link(_, text, _) => text,
-                    };
-                    if re.is_match(&text) {
-                        buf.curr_line = i;
-                        print_gemtext_line(&buf.lines[buf.curr_line]);
-                        return true;
+            // Writes default to the matched line rather than the whole buffer,
+            // and w only replaces the file on the first match, appending after.
+            let command = if command.starts_with(&['w', 'W'][..]) {
+                format!(".{}", command)
+            } else {
+                command
+            };
+            let mut written = false;
+            for i in matches {
+                buf.curr_line = i;
+                match interface::parse_response(format!("{}\n", command), buf)? {
+                    cmd @ ParseResponse::Print { .. } | cmd @ ParseResponse::Enumerate { .. } => {
+                        print_with_args(&cmd, buf, config)?;
+                    }
+                    ParseResponse::Write {
+                        start,
+                        stop,
+                        path,
+                        append,
+                        rendered,
+                    } => {
+                        write_lines(buf, start, stop, &path, append || written, rendered, config)?;
+                        written = true;
+                    }
+                    _ => {
+                        return Err(Error::command(
+                            "Only p, n, w and W can be used in a global command.",
+                        ))
                     }
                 }
             }
-            println!("?");
-            return true;
+        }
+        ParseResponse::SearchGlobalHistory(pattern) => {
+            let re = Regex::new(&pattern).map_err(Error::pattern)?;
+            for (n, visit) in hist.global.search(&re) {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    n,
+                    render::format_time(visit.time),
+                    visit.url,
+                    visit.title
+                );
+            }
+        }
+        ParseResponse::GoGlobalHistory(n) => {
+            let entry = hist
+                .global
+                .get(n)
+                .ok_or_else(|| Error::Command(format!("No history entry {}.", n)))?;
+            let url = url::Url::parse(&entry.url).map_err(|e| Error::url(&entry.url, e))?;
+            visit(&url, buf, hist, true, config).await?;
         }
         ParseResponse::Clear => {
             print!("{esc}c", esc = 27 as char);
-            return true;
         }
-        ParseResponse::AddBookmark(name) => {
-            if marks::add_bookmark(name, buf, marks).is_err() {
-                println!("FAILED TO ADD BOOKMARK"); // TEMP
+        ParseResponse::Write {
+            start,
+            stop,
+            path,
+            append,
+            rendered,
+        } => write_lines(buf, start, stop, &path, append, rendered, config)?,
+        ParseResponse::Info => print_info(buf),
+        ParseResponse::Shell(command) => shell::run(&command)?,
+        ParseResponse::ReadCommand(command) => {
+            let output = shell::read(&command)?;
+            let base = match &buf.url {
+                Some(url) => url.clone(),
+                None => url::Url::parse("file:///").map_err(|e| Error::url("file:///", e))?,
+            };
+            buf.lines = parse_gemtext(&output.replace("\r\n", "\n"), &base);
+            buf.curr_line = 0;
+            buf.url = None;
+            buf.redirects.clear();
+            buf.lang = None;
+            buf.info = None;
+            buf.line_marks.clear();
+            println!("{}", output.len());
+        }
+        ParseResponse::Outline => {
+            for (n, (i, level, text)) in headings(buf).into_iter().enumerate() {
+                println!("{}\t{}{}. {}", i + 1, "  ".repeat(level - 1), n + 1, text);
             }
-            return true;
         }
-        ParseResponse::GoBookmark(name) => {
-            if marks::go_to_bookmark(name, buf, hist, marks).await.is_err() {
-                println!("UNABLE TO GO TO BOOKMARK"); // TEMP
+        ParseResponse::JumpToHeading(n) => match headings(buf).get(n.wrapping_sub(1)) {
+            Some((i, _, _)) => {
+                buf.curr_line = *i;
+                print_gemtext_line(&buf.lines[buf.curr_line], None, config);
+            }
+            None => println!("?"),
+        },
+        ParseResponse::ToggleUrls => {
+            config.show_urls = !config.show_urls;
+            if config.show_urls {
+                println!("Showing link urls");
+            } else {
+                println!("Hiding link urls");
             }
-            return true;
         }
-        ParseResponse::Quit => return false,
+        ParseResponse::AddBookmark(name, tags) => marks::add_bookmark(&name, tags, buf, marks)?,
+        ParseResponse::GoBookmark(name) => {
+            marks::go_to_bookmark(&name, buf, hist, marks, config).await?
+        }
+        ParseResponse::ListBookmarks => marks::list_bookmarks(marks),
+        ParseResponse::DeleteBookmark(name) => {
+            marks::delete_bookmark(&name, marks)?;
+            println!("Deleted bookmark {}", name);
+        }
+        ParseResponse::ExportBookmarks(path) => marks::export_bookmarks(&path, marks)?,
+        ParseResponse::ImportBookmarks(None) => marks::import_bookmarks(&buf.lines, marks)?,
+        ParseResponse::ImportBookmarks(Some(path)) => {
+            let path = std::path::Path::new(&path);
+            let text =
+                std::fs::read_to_string(path).map_err(|e| Error::fs("Unable to read", path, e))?;
+            let abs =
+                std::fs::canonicalize(path).map_err(|e| Error::fs("Unable to read", path, e))?;
+            let base = url::Url::from_file_path(&abs)
+                .map_err(|_| Error::Command(format!("Unable to read {}.", path.display())))?;
+            marks::import_bookmarks(&parse_gemtext(&text.replace("\r\n", "\n"), &base), marks)?;
+        }
+        ParseResponse::RenameBookmark(name, new_name) => {
+            marks::rename_bookmark(&name, &new_name, marks)?;
+            println!("Renamed bookmark {} to {}", name, new_name);
+        }
+        ParseResponse::ListIdentities => identity::list(),
+        ParseResponse::NewIdentity(name) => {
+            identity::create(&name)?;
+            println!("Created identity {}", name);
+        }
+        ParseResponse::AttachIdentity(name, prefix) => {
+            identity::attach(&name, &prefix)?;
+            println!("Using {} for {}", name, prefix);
+        }
+        ParseResponse::RevokeIdentity(name) => {
+            identity::revoke(&name)?;
+            println!("Revoked identity {}", name);
+        }
+        ParseResponse::ListSessions => session::list(),
+        ParseResponse::SaveSession(name) => {
+            session::save(&name, buf, hist)?;
+            println!("Saved session {}", name);
+        }
+        ParseResponse::LoadSession(name) => session::restore(&name, buf, hist, config).await?,
+        ParseResponse::Quit => {}
         ParseResponse::Empty => {
             let cmd = ParseResponse::Print {
                 use_range: false,
                 start: 0,
                 stop: 0,
             };
-            if print_with_args(&cmd, buf).is_ok() {
-                return true;
-            }
+            print_with_args(&cmd, buf, config)?;
         }
         ParseResponse::Invalid => println!("?"),
     }
-    return true;
+    Ok(())
 }
 
-// Attempt to fetch a page.
-pub async fn go_url(url: &url::Url) -> StrResult<Page> {
-    if let Ok(page) = gemini_fetch::Page::fetch_and_handle_redirects(&url).await {
-        return Ok(page);
+// Fetch a url and load it into the buffer, printing the size of the body.
+pub async fn visit(
+    url: &url::Url,
+    buf: &mut PageBuf,
+    hist: &mut History,
+    add_to_hist: bool,
+    config: &config::Config,
+) -> ReiResult<()> {
+    let resp = go_url(url, config).await?;
+    if load_page(&resp, buf, hist, add_to_hist, config)? {
+        println!("{}", resp.data.len());
     }
-    return Err("Unable to fetch url.");
+    Ok(())
+}
+
+// Attempt to fetch a page, following redirects and prompting for input.
+// Any status other than success is returned as an error describing it.
+pub async fn go_url(url: &url::Url, config: &config::Config) -> ReiResult<fetch::Response> {
+    let mut url = url.clone();
+    let mut redirects: Vec<url::Url> = Vec::new();
+    loop {
+        let resp = fetch::fetch(&url).await?;
+        match fetch::Outcome::from_page(resp.page) {
+            fetch::Outcome::Success(page) => {
+                return Ok(fetch::Response {
+                    page,
+                    redirects,
+                    ..resp
+                })
+            }
+            fetch::Outcome::Redirect(target) => {
+                let next = url
+                    .join(target.trim())
+                    .map_err(|e| Error::url(target.trim(), e))?;
+                redirects.push(url);
+                if redirects.len() > config.redirect_limit {
+                    return Err(Error::Protocol(format!(
+                        "Too many redirects (limit is {}).",
+                        config.redirect_limit
+                    )));
+                }
+                if redirects.contains(&next) {
+                    return Err(Error::Protocol(format!(
+                        "Redirect loop detected at {}.",
+                        next
+                    )));
+                }
+                let prev = &redirects[redirects.len() - 1];
+                if config.confirm_redirects
+                    && (next.scheme() != prev.scheme() || next.host_str() != prev.host_str())
+                    && !interface::prompt_yes_no(&format!("Follow redirect to {}?", next))
+                {
+                    return Err(Error::command("Redirect not followed."));
+                }
+                println!("=> {}", next);
+                url = next;
+            }
+            fetch::Outcome::Input { prompt, sensitive } => {
+                let input = interface::prompt_input(&prompt, sensitive)
+                    .ok_or_else(|| Error::command("No input given."))?;
+                url.set_query(Some(&encode_query(&input)));
+            }
+            fetch::Outcome::TemporaryFailure(status, meta)
+            | fetch::Outcome::PermanentFailure(status, meta)
+            | fetch::Outcome::CertificateRequired(status, meta) => {
+                return Err(Error::Status { status, meta });
+            }
+        }
+    }
+}
+
+// Percent encode user input for use as a gemini query string.
+fn encode_query(input: &str) -> String {
+    use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+    const QUERY: &AsciiSet = &NON_ALPHANUMERIC
+        .remove(b'-')
+        .remove(b'.')
+        .remove(b'_')
+        .remove(b'~');
+    utf8_percent_encode(input, QUERY).to_string()
+}
+
+// Move to the next line matching pattern after (or before) the current one,
+// wrapping around the buffer, and print it. An empty pattern repeats the
+// previous search.
+fn search(
+    buf: &mut PageBuf,
+    pattern: &str,
+    forwards: bool,
+    config: &config::Config,
+) -> ReiResult<()> {
+    let re = last_pattern(buf, pattern.to_string())?;
+    let line = buf
+        .search(&re, forwards)
+        .ok_or_else(|| Error::Command(format!("No match for {}.", re)))?;
+    if (forwards && line <= buf.curr_line) || (!forwards && line >= buf.curr_line) {
+        println!("Search wrapped");
+    }
+    buf.curr_line = line;
+    print_gemtext_line(&buf.lines[line], Some(line + 1), config);
+    Ok(())
+}
+
+// Print every line with a match for pattern in the given scope, numbered and
+// with the matches highlighted.
+fn list_matches(buf: &mut PageBuf, pattern: String, scope: &SearchScope) -> ReiResult<()> {
+    let re = last_pattern(buf, pattern)?;
+    let mut found = false;
+    for (i, line) in buf.lines.iter().enumerate() {
+        let (prefix, text) = match (scope, line) {
+            (SearchScope::Text, GemTextLine::Link(id, _, _))
+            | (SearchScope::Links, GemTextLine::Link(id, Some(_), _)) => {
+                (format!("[{}] ", id), line.text())
+            }
+            (SearchScope::Urls, GemTextLine::Link(id, _, url)) => {
+                (format!("[{}] => ", id), url.as_str())
+            }
+            (SearchScope::Text, GemTextLine::H1(text))
+            | (SearchScope::Headings, GemTextLine::H1(text)) => ("# ".to_string(), text.as_str()),
+            (SearchScope::Text, GemTextLine::H2(text))
+            | (SearchScope::Headings, GemTextLine::H2(text)) => ("## ".to_string(), text.as_str()),
+            (SearchScope::Text, GemTextLine::H3(text))
+            | (SearchScope::Headings, GemTextLine::H3(text)) => ("### ".to_string(), text.as_str()),
+            (SearchScope::Text, _) => (String::new(), line.text()),
+            _ => continue,
+        };
+        let spans: Vec<(usize, usize)> = re
+            .find_iter(text)
+            .filter(|m| !m.as_str().is_empty())
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        if spans.is_empty() {
+            continue;
+        }
+        found = true;
+        for (row, highlighted) in render::highlight(&prefix, text, &spans)
+            .into_iter()
+            .enumerate()
+        {
+            if row == 0 {
+                println!("{}\t{}", i + 1, highlighted);
+            } else {
+                println!("\t{}", highlighted);
+            }
+        }
+    }
+    if !found {
+        return Err(Error::Command(format!("No match for {}.", re)));
+    }
+    Ok(())
+}
+
+// Compile a pattern, or the last one used if it is empty, and remember it.
+fn last_pattern(buf: &mut PageBuf, pattern: String) -> ReiResult<Regex> {
+    let pattern = if pattern.is_empty() {
+        buf.last_pattern
+            .clone()
+            .ok_or_else(|| Error::command("No previous pattern."))?
+    } else {
+        pattern
+    };
+    let re = Regex::new(&pattern).map_err(Error::pattern)?;
+    buf.last_pattern = Some(pattern);
+    Ok(re)
+}
+
+// Describe the page in the buffer and how it was fetched.
+fn print_info(buf: &PageBuf) {
+    let (url, info) = match (&buf.url, &buf.info) {
+        (Some(url), Some(info)) => (url, info),
+        _ => {
+            println!("No page loaded");
+            return;
+        }
+    };
+    let links = buf
+        .lines
+        .iter()
+        .filter(|line| matches!(line, GemTextLine::Link(..)))
+        .count();
+    println!("Url:         {}", url);
+    for redirect in &buf.redirects {
+        println!("Redirected:  {}", redirect);
+    }
+    println!("Status:      {} {}", info.status, info.meta);
+    println!(
+        "Size:        {} bytes, {} lines",
+        info.size,
+        buf.lines.len()
+    );
+    println!("Links:       {}", links);
+    println!("Language:    {}", buf.lang.as_deref().unwrap_or("unknown"));
+    println!(
+        "Fetched:     {} in {} ms",
+        render::format_time(info.fetched.timestamp()),
+        info.elapsed.as_millis()
+    );
+    println!("Certificate: {}", info.cert.fingerprint);
+    println!("Expires:     {}", tofu::format_expiry(info.cert.not_after));
+    println!("Trust:       {}", info.trust.describe());
+}
+
+// Every heading in the buffer as (line index, level, text).
+fn headings(buf: &PageBuf) -> Vec<(usize, usize, &str)> {
+    buf.lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| match line {
+            GemTextLine::H1(text) => Some((i, 1, text.as_str())),
+            GemTextLine::H2(text) => Some((i, 2, text.as_str())),
+            GemTextLine::H3(text) => Some((i, 3, text.as_str())),
+            _ => None,
+        })
+        .collect()
 }
 
 // Print part of the page
-fn print_with_args(cmd: &ParseResponse, buf: &mut PageBuf) -> StrResult<bool> {
-    return match cmd {
+fn print_with_args(
+    cmd: &ParseResponse,
+    buf: &mut PageBuf,
+    config: &config::Config,
+) -> ReiResult<bool> {
+    match cmd {
         ParseResponse::Print {
             use_range,
             start,
@@ -246,17 +542,19 @@ fn print_with_args(cmd: &ParseResponse, buf: &mut PageBuf) -> StrResult<bool> {
             if !use_range {
                 let start: usize = buf.curr_line;
                 if let Some(line) = buf.lines.get(start) {
-                    if let ParseResponse::Enumerate { .. } = cmd {
-                        print!("{}\t", start + 1);
-                    }
-                    print_gemtext_line(&line);
+                    print_block_alt(buf, start);
+                    let number = match cmd {
+                        ParseResponse::Enumerate { .. } => Some(start + 1),
+                        _ => None,
+                    };
+                    print_gemtext_line(line, number, config);
                     buf.curr_line += 1;
                     if buf.curr_line >= buf.lines.len() {
                         buf.curr_line = buf.lines.len() - 1;
                     }
                     return Ok(true);
                 }
-                return Ok(false);
+                Ok(false)
             } else {
                 let mut start = start;
                 let mut stop = stop;
@@ -270,110 +568,225 @@ fn print_with_args(cmd: &ParseResponse, buf: &mut PageBuf) -> StrResult<bool> {
                 }
                 let print_range = start..=stop;
                 for i in print_range {
-                    if let ParseResponse::Enumerate { .. } = cmd {
-                        print!("{}\t", i + 1);
-                    }
+                    print_block_alt(buf, i);
+                    let number = match cmd {
+                        ParseResponse::Enumerate { .. } => Some(i + 1),
+                        _ => None,
+                    };
                     if let Some(line) = buf.lines.get(i) {
-                        print_gemtext_line(&line);
+                        print_gemtext_line(line, number, config);
                     }
                 }
                 buf.curr_line = stop;
-                return Ok(true);
+                Ok(true)
             }
         }
-        _ => Err("BAD THINGS HAPPENED"),
-    };
+        _ => Err(Error::command("Not a print command.")),
+    }
 }
 
-fn print_gemtext_line(line: &GemTextLine) {
-    match line {
-        GemTextLine::H1(str) => println!("{}", str),
-        GemTextLine::H2(str) => println!("{}", str),
-        GemTextLine::H3(str) => println!("{}", str),
-        GemTextLine::Line(str) => println!("{}", str),
-        GemTextLine::Link(id, text, _) => println!("[{}] => {}", id, text),
+// Print a line wrapped to the terminal, optionally numbered.
+// Returns the number of rows printed.
+fn print_gemtext_line(line: &GemTextLine, number: Option<usize>, config: &config::Config) -> usize {
+    let mut width = render::width(config);
+    if number.is_some() {
+        width = width.saturating_sub(8); // Room for the number and tab.
+    }
+    let rows = render::render_line(line, config, width);
+    for (i, row) in rows.iter().enumerate() {
+        match number {
+            Some(n) if i == 0 => println!("{}\t{}", n, row),
+            Some(_) => println!("\t{}", row),
+            None => println!("{}", row),
+        }
+    }
+    rows.len()
+}
+
+// Announce a preformatted block, and its alt text, before its first line.
+// Returns the number of rows printed.
+fn print_block_alt(buf: &PageBuf, i: usize) -> usize {
+    match render::block_alt(&buf.lines, i) {
+        Some(alt) => {
+            println!("{}", alt);
+            1
+        }
+        None => 0,
     }
 }
 
-// Load a fetched page into the PageBuf and history.
+// Write lines start through stop to a file as gemtext or rendered text,
+// printing the number of bytes written. A path starting with ! is a shell
+// command to pipe the lines to instead.
+fn write_lines(
+    buf: &PageBuf,
+    start: usize,
+    stop: usize,
+    path: &str,
+    append: bool,
+    rendered: bool,
+    config: &config::Config,
+) -> ReiResult<()> {
+    let lines = match buf
+        .lines
+        .get(start..=stop.min(buf.lines.len().saturating_sub(1)))
+    {
+        Some(lines) if !buf.lines.is_empty() => lines,
+        _ => return Err(Error::command("Nothing to write.")),
+    };
+    let rows = if rendered {
+        let width = render::width(config);
+        let mut rows = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            rows.extend(render::block_alt(lines, i));
+            rows.extend(render::render_line(line, config, width));
+        }
+        rows
+    } else {
+        render::gemtext(lines)
+    };
+    let mut out = String::new();
+    for row in rows {
+        out.push_str(&row);
+        out.push('\n');
+    }
+    if let Some(command) = path.strip_prefix('!') {
+        return shell::pipe(command, out.as_bytes());
+    }
+    let path = std::path::Path::new(path);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|e| Error::fs("Unable to open", path, e))?;
+    file.write_all(out.as_bytes())
+        .map_err(|e| Error::fs("Unable to write to", path, e))?;
+    println!("{}", out.len());
+    Ok(())
+}
+
+// Load a fetched page into the PageBuf and history. Gemtext is parsed, other
+// text is loaded line by line and anything else is offered for download.
+// Returns false if the buffer was left alone.
 pub fn load_page(
-    raw: &gemini_fetch::Page,
+    resp: &fetch::Response,
     buf: &mut PageBuf,
     hist: &mut History,
     add_to_hist: bool,
-) -> StrResult<bool> {
-    if raw.header.meta.starts_with("text/gemini") {
-        if let Some(body) = &raw.body {
-            buf.lines.clear();
-            let mut link_count: usize = 0;
-            let mut lines = body.split('\n');
-            buf.curr_line = 0;
-            while let Some(line) = lines.next() {
-                if line.starts_with('#') {
-                    if let Ok(parsed) = parse_gemtext_header(line) {
-                        if !buf.lines.is_empty() {
-                            buf.lines.push(GemTextLine::Line("".to_string()));
-                        }
-                        buf.lines.push(parsed);
-                        buf.lines.push(GemTextLine::Line("".to_string()));
-                    }
-                } else if line.starts_with("=>") {
-                    if let Ok(parsed) = parse_gemtext_link(line, &mut link_count, &raw.url) {
-                        buf.lines.push(parsed);
-                    } else {
-                        println!("Unable to parse link: {}", line);
-                    }
-                } else if line.starts_with("```") {
-                    while let Some(line) = lines.next() {
-                        if line.starts_with("```") {
-                            break;
-                        } else {
-                            buf.lines.push(GemTextLine::Line(line.to_string()));
-                        }
-                    }
-                } else {
-                    buf.lines.push(GemTextLine::Line(line.to_string()));
-                }
-            }
-            if add_to_hist {
-                hist.add(&raw.url);
-            }
-            if let Ok(new_url) = url::Url::parse(raw.url.as_str()) {
-                buf.url = Some(new_url);
+    config: &config::Config,
+) -> ReiResult<bool> {
+    let raw = &resp.page;
+    let mime = mime::Mime::parse(&raw.header.meta);
+    if !mime.is_text() {
+        download::save(resp, config)?;
+        return Ok(false);
+    }
+    let body = mime.decode(&resp.data)?;
+    buf.lines = if mime.is_gemtext() {
+        parse_gemtext(&body.replace("\r\n", "\n"), &raw.url)
+    } else {
+        body.lines()
+            .map(|line| GemTextLine::Line(line.to_string()))
+            .collect()
+    };
+    buf.lang = mime.lang;
+    buf.info = Some(PageInfo {
+        status: fetch::status_code(&raw.header.status),
+        meta: raw.header.meta.clone(),
+        size: resp.data.len(),
+        fetched: resp.fetched,
+        elapsed: resp.elapsed,
+        cert: resp.cert.clone(),
+        trust: resp.trust,
+    });
+    buf.curr_line = 0;
+    buf.line_marks.clear();
+    if add_to_hist {
+        if config.history_redirects {
+            for url in &resp.redirects {
+                hist.add(url);
             }
         }
-    } else {
-        println!("NOT GEMINI: {}", raw.url.as_str());
-        println!("{}", raw.header.meta);
-        return Err("Unable to load page!");
+        hist.add(&raw.url);
+    }
+    buf.redirects = resp.redirects.clone();
+    if let Ok(new_url) = url::Url::parse(raw.url.as_str()) {
+        buf.url = Some(new_url);
+    }
+    // The page is loaded either way, so failing to remember it is only a warning.
+    if let Err(e) = hist.global.record(&raw.url, buf.title().unwrap_or("")) {
+        println!("Warning: {}", e);
     }
     Ok(true)
 }
 
-// Parse a gemtext header (i.e. "#{1,3}")
-fn parse_gemtext_header(text: &str) -> StrResult<GemTextLine> {
-    let mut header_count = 0;
-    for c in text.chars() {
-        if c == '#' {
-            header_count += 1;
-        } else if header_count <= 3 {
-            return Ok(match header_count {
-                // TODO: Strip off header signifiers.
-                1 => GemTextLine::H1(text.to_string()),
-                2 => GemTextLine::H2(text.to_string()),
-                3 => GemTextLine::H3(text.to_string()),
-                _ => GemTextLine::Line(text.to_string()),
-            });
+// Parse a gemtext document into buffer lines.
+pub fn parse_gemtext(body: &str, base_url: &url::Url) -> Vec<GemTextLine> {
+    let mut parsed_lines = Vec::new();
+    let mut link_count: usize = 0;
+    let mut block_count: usize = 0;
+    let mut lines = body.split('\n');
+    while let Some(line) = lines.next() {
+        if line.starts_with('#') {
+            match parse_gemtext_header(line) {
+                Ok(parsed) => {
+                    if !parsed_lines.is_empty() {
+                        parsed_lines.push(GemTextLine::Line("".to_string()));
+                    }
+                    parsed_lines.push(parsed);
+                    parsed_lines.push(GemTextLine::Line("".to_string()));
+                }
+                // More than three #s is just text.
+                Err(_) => parsed_lines.push(GemTextLine::Line(line.to_string())),
+            }
+        } else if line.starts_with("=>") {
+            match parse_gemtext_link(line, &mut link_count, base_url) {
+                Ok(parsed) => parsed_lines.push(parsed),
+                Err(e) => println!("{}", e),
+            }
+        } else if let Some(alt) = line.strip_prefix("```") {
+            block_count += 1;
+            let alt = match alt.trim() {
+                "" => None,
+                alt => Some(alt.to_string()),
+            };
+            for line in lines.by_ref() {
+                if line.starts_with("```") {
+                    break;
+                }
+                parsed_lines.push(GemTextLine::Preformatted(
+                    block_count,
+                    alt.clone(),
+                    line.to_string(),
+                ));
+            }
+        } else if let Some(text) = line.strip_prefix('>') {
+            parsed_lines.push(GemTextLine::Quote(text.trim_start().to_string()));
+        } else if let Some(text) = line.strip_prefix("* ") {
+            parsed_lines.push(GemTextLine::ListItem(text.trim_start().to_string()));
         } else {
-            break;
+            parsed_lines.push(GemTextLine::Line(line.to_string()));
         }
     }
+    parsed_lines
+}
 
-    Err("Unable to parse header.")
+// Parse a gemtext header (i.e. "#{1,3}"), stripping the signifiers.
+fn parse_gemtext_header(text: &str) -> ReiResult<GemTextLine> {
+    let level = text.chars().take_while(|c| *c == '#').count();
+    let heading = text[level..].trim().to_string();
+    match level {
+        1 => Ok(GemTextLine::H1(heading)),
+        2 => Ok(GemTextLine::H2(heading)),
+        3 => Ok(GemTextLine::H3(heading)),
+        _ => Err(Error::Protocol(format!("Unable to parse header: {}", text))),
+    }
 }
 
 // Parse a gemtext link (i.e. "=> url [text]")
-fn parse_gemtext_link(line: &str, id: &mut usize, curr_url: &url::Url) -> StrResult<GemTextLine> {
+fn parse_gemtext_link(line: &str, id: &mut usize, curr_url: &url::Url) -> ReiResult<GemTextLine> {
     lazy_static! {
         static ref WHITESPACE_ONLY: regex::Regex = Regex::new(r"^\s*$").unwrap();
         static ref LINK_REGEX: regex::Regex = Regex::new(r"^=>\s+([^\s]+)\s+(.+)$").unwrap();
@@ -398,13 +811,13 @@ fn parse_gemtext_link(line: &str, id: &mut usize, curr_url: &url::Url) -> StrRes
 
     if LINK_REGEX.is_match(line) {
         if let Some(captures) = LINK_REGEX.captures(line) {
-            if let (Some(url_str), Some(_)) = (captures.get(1), captures.get(2)) {
+            if let (Some(url_str), Some(label)) = (captures.get(1), captures.get(2)) {
                 let new_url = fix_url(url_str.as_str(), curr_url);
                 if let Ok(parsed_url) = url::Url::parse(new_url.as_str()) {
                     *id += 1;
                     return Ok(GemTextLine::Link(
                         *id,
-                        new_url.as_str().to_string(),
+                        Some(label.as_str().trim().to_string()),
                         parsed_url,
                     ));
                 }
@@ -416,15 +829,11 @@ fn parse_gemtext_link(line: &str, id: &mut usize, curr_url: &url::Url) -> StrRes
                 let new_url = fix_url(url_str.as_str(), curr_url);
                 if let Ok(parsed_url) = url::Url::parse(new_url.as_str()) {
                     *id += 1;
-                    return Ok(GemTextLine::Link(
-                        *id,
-                        new_url.as_str().to_string(),
-                        parsed_url,
-                    ));
+                    return Ok(GemTextLine::Link(*id, None, parsed_url));
                 }
             }
         }
     }
 
-    Err("Unable to parse link.")
+    Err(Error::Protocol(format!("Unable to parse link: {}", line)))
 }
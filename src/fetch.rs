@@ -0,0 +1,321 @@
+use crate::*;
+use rustls::Session;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+/// Low level gemini requests.
+// Certificates are never rejected during the handshake. Trust is decided
+// afterwards against the known hosts store (see tofu.rs).
+struct AcceptAnyCert;
+
+impl rustls::ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15); // For each of connecting and the handshake.
+const READ_TIMEOUT: Duration = Duration::from_secs(30); // Longest wait for more of the response.
+const IP_HOST_NAME: &str = "ip-address.invalid"; // Handshake name for IP address hosts.
+
+/// The parts of a server certificate that get pinned.
+#[derive(Clone)]
+pub struct ServerCert {
+    pub fingerprint: String,    // Hex encoded SHA-256 of the DER certificate.
+    pub not_after: Option<i64>, // Expiry as a unix timestamp, if it could be read.
+}
+
+impl ServerCert {
+    fn from_der(der: &[u8]) -> ServerCert {
+        let digest = ring::digest::digest(&ring::digest::SHA256, der);
+        let fingerprint = digest
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        // x509_signature rejects v1 certificates and some key types, whose
+        // expiry is then left unknown.
+        let not_after = x509_signature::parse_certificate(der)
+            .ok()
+            .map(|cert| cert.not_after().into());
+        ServerCert {
+            fingerprint,
+            not_after,
+        }
+    }
+}
+
+/// A successfully fetched page and the redirects followed to reach it.
+pub struct Response {
+    pub page: Page,
+    pub data: Vec<u8>, // The body exactly as it was received.
+    pub redirects: Vec<url::Url>,
+    pub cert: ServerCert,
+    pub trust: tofu::Trust,
+    pub fetched: chrono::DateTime<chrono::Local>,
+    pub elapsed: std::time::Duration, // Time taken by the final request.
+}
+
+/// A response sorted by status class.
+pub enum Outcome {
+    Input { prompt: String, sensitive: bool },
+    Success(Page),
+    Redirect(String),                 // Redirect target.
+    TemporaryFailure(Status, String), // Status and the server's message.
+    PermanentFailure(Status, String),
+    CertificateRequired(Status, String),
+}
+
+impl Outcome {
+    pub fn from_page(page: Page) -> Outcome {
+        let meta = page.header.meta.clone();
+        match page.header.status {
+            Status::Input => Outcome::Input {
+                prompt: meta,
+                sensitive: false,
+            },
+            Status::SensitiveInput => Outcome::Input {
+                prompt: meta,
+                sensitive: true,
+            },
+            Status::Success => Outcome::Success(page),
+            Status::TemporaryRedirect | Status::PermanentRedirect => Outcome::Redirect(meta),
+            status @ Status::TemporaryFailure
+            | status @ Status::ServerUnavailable
+            | status @ Status::CgiError
+            | status @ Status::ProxyError
+            | status @ Status::SlowDown => Outcome::TemporaryFailure(status, meta),
+            status @ Status::PermanentFailure
+            | status @ Status::NotFound
+            | status @ Status::Gone
+            | status @ Status::ProxyRequestRefused
+            | status @ Status::BadRequest => Outcome::PermanentFailure(status, meta),
+            status @ Status::ClientCertificateRequired
+            | status @ Status::CertificateNotAuthorized
+            | status @ Status::CertificateNotValid => Outcome::CertificateRequired(status, meta),
+        }
+    }
+}
+
+pub fn status_code(status: &Status) -> u8 {
+    match status {
+        Status::Input => 10,
+        Status::SensitiveInput => 11,
+        Status::Success => 20,
+        Status::TemporaryRedirect => 30,
+        Status::PermanentRedirect => 31,
+        Status::TemporaryFailure => 40,
+        Status::ServerUnavailable => 41,
+        Status::CgiError => 42,
+        Status::ProxyError => 43,
+        Status::SlowDown => 44,
+        Status::PermanentFailure => 50,
+        Status::NotFound => 51,
+        Status::Gone => 52,
+        Status::ProxyRequestRefused => 53,
+        Status::BadRequest => 59,
+        Status::ClientCertificateRequired => 60,
+        Status::CertificateNotAuthorized => 61,
+        Status::CertificateNotValid => 62,
+    }
+}
+
+// A short user facing description of a status.
+pub fn status_message(status: &Status) -> &'static str {
+    match status {
+        Status::Input | Status::SensitiveInput => "Input requested",
+        Status::Success => "Success",
+        Status::TemporaryRedirect => "Temporary redirect",
+        Status::PermanentRedirect => "Permanent redirect",
+        Status::TemporaryFailure => "Temporary failure",
+        Status::ServerUnavailable => "Server unavailable",
+        Status::CgiError => "CGI error",
+        Status::ProxyError => "Proxy error",
+        Status::SlowDown => "Slow down",
+        Status::PermanentFailure => "Permanent failure",
+        Status::NotFound => "Not found",
+        Status::Gone => "Gone",
+        Status::ProxyRequestRefused => "Proxy request refused",
+        Status::BadRequest => "Bad request",
+        Status::ClientCertificateRequired => "Client certificate required",
+        Status::CertificateNotAuthorized => "Certificate not authorized",
+        Status::CertificateNotValid => "Certificate not valid",
+    }
+}
+
+// Parse a two digit status code. Codes gemini_fetch does not know are treated
+// as the basic code of their class, e.g. 21 as 20 and 45 as 40.
+fn parse_status(code: &str) -> Option<Status> {
+    if code.len() != 2 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(status) = code.parse::<Status>() {
+        return Some(status);
+    }
+    match &code[..1] {
+        "1" => Some(Status::Input),
+        "2" => Some(Status::Success),
+        "3" => Some(Status::TemporaryRedirect),
+        "4" => Some(Status::TemporaryFailure),
+        "5" => Some(Status::PermanentFailure),
+        "6" => Some(Status::ClientCertificateRequired),
+        _ => None,
+    }
+}
+
+// Fetch a single url, checking the server certificate against the known hosts.
+// Does not follow redirects. The page body is only set when it is valid UTF-8.
+pub async fn fetch(url: &url::Url) -> ReiResult<Response> {
+    let fetched = chrono::Local::now();
+    let start = std::time::Instant::now();
+    let (stream, cert) = connect(url).await?;
+    let trust = tofu::verify(url, &cert)?;
+    let (page, data) = request(stream, url).await?;
+    Ok(Response {
+        page,
+        data,
+        redirects: Vec::new(),
+        cert,
+        trust,
+        fetched,
+        elapsed: start.elapsed(),
+    })
+}
+
+// Open a TLS connection to the host in url and grab its certificate.
+async fn connect(url: &url::Url) -> ReiResult<(TlsStream<TcpStream>, ServerCert)> {
+    if url.scheme() != "gemini" {
+        return Err(Error::command("Only gemini urls are supported."));
+    }
+    let port = url.port().unwrap_or(1965);
+    // SNI can not carry an IP address, so it is left out and the handshake is
+    // given a placeholder name instead. Gemini urls are not special to the url
+    // crate, so IPv4 addresses arrive as domains.
+    let (host, sni) = match url.host() {
+        Some(url::Host::Domain(domain)) => (
+            domain.to_string(),
+            domain.parse::<std::net::IpAddr>().is_err(),
+        ),
+        Some(url::Host::Ipv4(ip)) => (ip.to_string(), false),
+        Some(url::Host::Ipv6(ip)) => (ip.to_string(), false),
+        None => return Err(Error::command("Missing host in url.")),
+    };
+    let dns_name = if sni { host.as_str() } else { IP_HOST_NAME };
+    let dns_name = webpki::DNSNameRef::try_from_ascii_str(dns_name)
+        .map_err(|e| Error::tls(&format!("Invalid host name {}", host), e))?;
+
+    let mut config = rustls::ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(AcceptAnyCert));
+    config.enable_sni = sni;
+    if let Some(id) = identity::find(url) {
+        config
+            .set_single_client_cert(
+                vec![rustls::Certificate(id.cert)],
+                rustls::PrivateKey(id.key),
+            )
+            .map_err(|e| Error::tls("Unable to use client certificate", e))?;
+    }
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let addr = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| Error::network(&format!("Unable to resolve {}", host), e))?
+        .next()
+        .ok_or_else(|| Error::Network {
+            context: format!("No addresses found for {}", host),
+            source: None,
+        })?;
+    let socket = timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr))
+        .await
+        .map_err(|_| timed_out(&format!("Timed out connecting to {}", addr)))?
+        .map_err(|e| Error::network(&format!("Unable to connect to {}", addr), e))?;
+    let stream = timeout(CONNECT_TIMEOUT, connector.connect(dns_name, socket))
+        .await
+        .map_err(|_| timed_out("Timed out during the TLS handshake"))?
+        .map_err(|e| Error::tls("TLS handshake failed", e))?;
+
+    let cert = match stream.get_ref().1.get_peer_certificates() {
+        Some(certs) if !certs.is_empty() => ServerCert::from_der(certs[0].as_ref()),
+        _ => {
+            return Err(Error::Tls {
+                context: "Server did not present a certificate".to_string(),
+                source: None,
+            })
+        }
+    };
+    Ok((stream, cert))
+}
+
+// Send the request line and read back the whole response.
+async fn request(mut stream: TlsStream<TcpStream>, url: &url::Url) -> ReiResult<(Page, Vec<u8>)> {
+    stream
+        .write_all(format!("{}\r\n", url).as_bytes())
+        .await
+        .map_err(|e| Error::network("Unable to send request", e))?;
+    let mut data = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        match timeout(READ_TIMEOUT, stream.read(&mut chunk)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => data.extend_from_slice(&chunk[..n]),
+            // Plenty of servers close without a TLS close_notify, so keep whatever arrived.
+            Ok(Err(e)) => {
+                if data.is_empty() {
+                    return Err(Error::network("Unable to read response", e));
+                }
+                break;
+            }
+            Err(_) => return Err(timed_out("Timed out waiting for the server")),
+        }
+    }
+
+    let split = data
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or_else(|| Error::Protocol("Response is missing its header.".to_string()))?;
+    let body = data.split_off(split + 1);
+    let response = String::from_utf8(data)
+        .map_err(|_| Error::Protocol("Response header is not valid UTF-8.".to_string()))?;
+    // The meta string may be empty (e.g. "10\r\n"), which Header::from_str rejects.
+    let mut parts = response.trim().splitn(2, ' ');
+    let status = parts
+        .next()
+        .and_then(parse_status)
+        .ok_or_else(|| Error::Protocol(format!("Invalid response header: {}", response.trim())))?;
+    let header = gemini_fetch::Header {
+        status,
+        meta: parts.next().unwrap_or("").trim().to_string(),
+    };
+
+    let text = match std::str::from_utf8(&body) {
+        Ok(text) if !text.is_empty() => Some(text.replace("\r\n", "\n")),
+        _ => None,
+    };
+    Ok((
+        Page {
+            url: url.clone(),
+            header,
+            body: text,
+        },
+        body,
+    ))
+}
+
+fn timed_out(context: &str) -> Error {
+    Error::network(
+        context,
+        std::io::Error::new(std::io::ErrorKind::TimedOut, "no response"),
+    )
+}
@@ -0,0 +1,91 @@
+use crate::*;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Every page visited, across sessions.
+// Kept in <config dir>/rei/history, one "timestamp<TAB>url<TAB>title" entry per
+// line, oldest first. New visits are appended as they happen.
+pub struct Visit {
+    pub time: i64, // Unix timestamp of the visit.
+    pub url: String,
+    pub title: String, // First level one heading, may be empty.
+}
+
+pub struct GlobalHistory {
+    visits: Vec<Visit>,
+}
+
+impl GlobalHistory {
+    pub fn load() -> GlobalHistory {
+        let mut visits = Vec::new();
+        if let Some(path) = history_path() {
+            if let Ok(mut file) = OpenOptions::new().read(true).open(path) {
+                let mut buf = String::new();
+                if file.read_to_string(&mut buf).is_err() {
+                    return GlobalHistory { visits };
+                }
+                for line in buf.lines() {
+                    let mut split = line.splitn(3, '\t');
+                    if let (Some(time), Some(url)) = (split.next(), split.next()) {
+                        if let Ok(time) = time.parse::<i64>() {
+                            visits.push(Visit {
+                                time,
+                                url: url.to_string(),
+                                title: split.next().unwrap_or("").to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        GlobalHistory { visits }
+    }
+
+    // Remember a visit and append it to the history file.
+    pub fn record(&mut self, url: &url::Url, title: &str) -> ReiResult<()> {
+        let visit = Visit {
+            time: chrono::Utc::now().timestamp(),
+            url: url.to_string(),
+            title: title.replace(&['\t', '\n', '\r'][..], " "),
+        };
+        let path = history_path().ok_or(Error::NoConfigDir)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::fs("Unable to create", dir, e))?;
+        }
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| Error::fs("Unable to open", &path, e))?;
+        file.write_fmt(format_args!(
+            "{}\t{}\t{}\n",
+            visit.time, visit.url, visit.title
+        ))
+        .map_err(|e| Error::fs("Unable to write to", &path, e))?;
+        self.visits.push(visit);
+        Ok(())
+    }
+
+    // Visits whose url or title match re, numbered from 1.
+    pub fn search(&self, re: &Regex) -> Vec<(usize, &Visit)> {
+        self.visits
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| re.is_match(&v.url) || re.is_match(&v.title))
+            .map(|(i, v)| (i + 1, v))
+            .collect()
+    }
+
+    // The visit numbered n, counting from 1.
+    pub fn get(&self, n: usize) -> Option<&Visit> {
+        self.visits.get(n.wrapping_sub(1))
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("rei");
+    path.push("history");
+    Some(path)
+}
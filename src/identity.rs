@@ -0,0 +1,209 @@
+use crate::*;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Client certificate identities.
+// Each identity is a self signed certificate stored as <name>.crt and <name>.key
+// (DER) under <config dir>/rei/identities. The "scopes" file in the same
+// directory attaches identities to url prefixes, one "name prefix" per line.
+pub struct Identity {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+struct Scope {
+    name: String,
+    prefix: String,
+}
+
+// Generate a new self signed identity.
+pub fn create(name: &str) -> ReiResult<()> {
+    if !valid_name(name) {
+        return Err(Error::command(
+            "Identity names may only use letters, numbers, - and _.",
+        ));
+    }
+    let dir = identities_dir().ok_or(Error::NoConfigDir)?;
+    let cert_path = dir.join(format!("{}.crt", name));
+    if cert_path.exists() {
+        return Err(Error::Command(format!("Identity {} already exists.", name)));
+    }
+    let mut params = rcgen::CertificateParams::new(Vec::new());
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, name);
+    let cert = rcgen::Certificate::from_params(params).map_err(generate_error)?;
+    let der = cert.serialize_der().map_err(generate_error)?;
+
+    std::fs::create_dir_all(&dir).map_err(|e| Error::fs("Unable to create", &dir, e))?;
+    write_file(
+        &dir.join(format!("{}.key", name)),
+        &cert.serialize_private_key_der(),
+    )?;
+    write_file(&cert_path, &der)?;
+    Ok(())
+}
+
+// Attach an identity to every url starting with prefix.
+pub fn attach(name: &str, prefix: &url::Url) -> ReiResult<()> {
+    if !exists(name) {
+        return Err(Error::Command(format!("No identity named {}.", name)));
+    }
+    let mut scopes = load_scopes();
+    scopes.retain(|s| s.prefix != prefix.as_str());
+    scopes.push(Scope {
+        name: name.to_string(),
+        prefix: prefix.as_str().to_string(),
+    });
+    save_scopes(&scopes)
+}
+
+// Delete an identity and everything it is attached to.
+pub fn revoke(name: &str) -> ReiResult<()> {
+    if !exists(name) {
+        return Err(Error::Command(format!("No identity named {}.", name)));
+    }
+    let dir = identities_dir().ok_or(Error::NoConfigDir)?;
+    let cert_path = dir.join(format!("{}.crt", name));
+    std::fs::remove_file(&cert_path).map_err(|e| Error::fs("Unable to remove", &cert_path, e))?;
+    let _ = std::fs::remove_file(dir.join(format!("{}.key", name)));
+    let mut scopes = load_scopes();
+    scopes.retain(|s| s.name != name);
+    save_scopes(&scopes)
+}
+
+// Print every identity along with the prefixes it is used for.
+pub fn list() {
+    let mut names = Vec::new();
+    if let Some(dir) = identities_dir() {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) == Some("crt") {
+                    if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+    }
+    names.sort();
+    let scopes = load_scopes();
+    for name in names {
+        println!("{}", name);
+        for scope in scopes.iter().filter(|s| s.name == name) {
+            println!("\t{}", scope.prefix);
+        }
+    }
+}
+
+// Find the identity to present for url, preferring the longest matching prefix.
+pub fn find(url: &url::Url) -> Option<Identity> {
+    let scope = load_scopes()
+        .into_iter()
+        .filter(|s| in_scope(url, &s.prefix))
+        .max_by_key(|s| s.prefix.len())?;
+    let dir = identities_dir()?;
+    let cert = read_file(&dir.join(format!("{}.crt", scope.name)))?;
+    let key = read_file(&dir.join(format!("{}.key", scope.name)))?;
+    Some(Identity { cert, key })
+}
+
+// Whether url is covered by prefix: the scheme, host and port must be the same
+// and the path must equal the prefix's or continue it after a "/".
+fn in_scope(url: &url::Url, prefix: &str) -> bool {
+    let prefix = match url::Url::parse(prefix) {
+        Ok(prefix) => prefix,
+        Err(_) => return false,
+    };
+    let same_host = match (url.host_str(), prefix.host_str()) {
+        (Some(host), Some(prefix_host)) => host.eq_ignore_ascii_case(prefix_host),
+        _ => false,
+    };
+    if url.scheme() != prefix.scheme()
+        || !same_host
+        || url.port().unwrap_or(1965) != prefix.port().unwrap_or(1965)
+    {
+        return false;
+    }
+    match url.path().strip_prefix(prefix.path().trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+fn generate_error(e: rcgen::RcgenError) -> Error {
+    Error::tls("Unable to generate certificate", e)
+}
+
+fn exists(name: &str) -> bool {
+    valid_name(name)
+        && identities_dir()
+            .map(|dir| dir.join(format!("{}.crt", name)).exists())
+            .unwrap_or(false)
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn load_scopes() -> Vec<Scope> {
+    let mut scopes = Vec::new();
+    if let Some(dir) = identities_dir() {
+        if let Some(buf) = read_file(&dir.join("scopes")) {
+            for line in String::from_utf8_lossy(&buf).lines() {
+                let mut split = line.split_whitespace();
+                if let (Some(name), Some(prefix)) = (split.next(), split.next()) {
+                    scopes.push(Scope {
+                        name: name.to_string(),
+                        prefix: prefix.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    scopes
+}
+
+fn save_scopes(scopes: &[Scope]) -> ReiResult<()> {
+    let dir = identities_dir().ok_or(Error::NoConfigDir)?;
+    let mut buf = String::new();
+    for s in scopes {
+        buf.push_str(&format!("{} {}\n", s.name, s.prefix));
+    }
+    write_file(&dir.join("scopes"), buf.as_bytes())
+}
+
+fn read_file(path: &std::path::Path) -> Option<Vec<u8>> {
+    let mut file = OpenOptions::new().read(true).open(path).ok()?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).ok()?;
+    Some(buf)
+}
+
+fn write_file(path: &std::path::Path, data: &[u8]) -> ReiResult<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| Error::fs("Unable to open", path, e))?;
+    file.write_all(data)
+        .map_err(|e| Error::fs("Unable to write to", path, e))
+}
+
+fn identities_dir() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("rei");
+    path.push("identities");
+    Some(path)
+}
@@ -1,39 +1,161 @@
 use crate::*;
 
 /// Functions for user interaction.
+// The start of an address: a line number, ".", "$", a line mark or a pattern.
+const ADDRESS_BASE: &str = r"[0-9]+|\.|\$|'[a-z]|/(?:[^/\\]|\\.)*/|\?(?:[^?\\]|\\.)*\?";
+lazy_static! {
+    // A whole address: a start with optional offsets, or offsets alone.
+    static ref ADDRESS: String = format!(r"(?:{})(?:[\+-][0-9]*)*|(?:[\+-][0-9]*)+", ADDRESS_BASE);
+}
+
 // Prompt for input and return the command.
-pub fn prompt(buf: &PageBuf) -> StrResult<ParseResponse> {
+pub fn prompt(buf: &PageBuf) -> ReiResult<ParseResponse> {
     print!("*");
     let _ = std::io::stdout().flush();
     let mut response = String::new();
-    let _bytes_read = std::io::stdin().read_line(&mut response).unwrap();
+    let bytes_read = std::io::stdin().read_line(&mut response).unwrap();
+    if bytes_read == 0 {
+        return Ok(ParseResponse::Quit); // End of input.
+    }
     parse_response(response, buf)
 }
 
+// Ask what to do about a changed server certificate.
+pub fn prompt_cert_choice() -> tofu::CertChoice {
+    loop {
+        print!("(a)ccept, (r)eject or accept (o)nce? ");
+        let _ = std::io::stdout().flush();
+        let mut response = String::new();
+        if std::io::stdin().read_line(&mut response).is_err() {
+            return tofu::CertChoice::Reject;
+        }
+        match response.trim() {
+            "a" => return tofu::CertChoice::Accept,
+            "o" => return tofu::CertChoice::AcceptOnce,
+            "r" | "" => return tofu::CertChoice::Reject,
+            _ => println!("?"),
+        }
+    }
+}
+
+// Ask a yes or no question, defaulting to no.
+pub fn prompt_yes_no(question: &str) -> bool {
+    print!("{} (y/n) ", question);
+    let _ = std::io::stdout().flush();
+    let mut response = String::new();
+    if std::io::stdin().read_line(&mut response).is_err() {
+        return false;
+    }
+    matches!(response.trim(), "y" | "yes")
+}
+
+// Ask for the input a server requested with a 10 or 11 status.
+// Sensitive input is read without echoing it back.
+pub fn prompt_input(meta: &str, sensitive: bool) -> Option<String> {
+    let prompt = match meta.trim() {
+        "" => "Input: ".to_string(),
+        meta => format!("{}: ", meta),
+    };
+    let response = if sensitive {
+        rpassword::prompt_password_stdout(&prompt).ok()?
+    } else {
+        print!("{}", prompt);
+        let _ = std::io::stdout().flush();
+        let mut response = String::new();
+        std::io::stdin().read_line(&mut response).ok()?;
+        response
+    };
+    let response = response.trim_end_matches(&['\n', '\r'][..]);
+    if response.is_empty() {
+        None
+    } else {
+        Some(response.to_string())
+    }
+}
+
 // Parse the users command.
 // Called by prompt to match input to commands.
-fn parse_response(resp: String, buf: &PageBuf) -> StrResult<ParseResponse> {
+pub fn parse_response(resp: String, buf: &PageBuf) -> ReiResult<ParseResponse> {
     lazy_static! {
-            static ref NUM_REGEX : regex::Regex = Regex::new(r"^([\+-]+[0-9]+|[0-9]+|\$)\s*$").unwrap();                    // Number only
-            static ref NUM_LETTER_REGEX : regex::Regex = Regex::new(r"^(%|[\+-]+[0-9]+|[0-9]+)([a-z]+)\s*$").unwrap();     // Number and letter
-            static ref RANGE_LETTER : regex::Regex = Regex::new(r"^([\+-]+[0-9]+|[0-9]+|\.),([\+-]+[0-9]+|[0-9]+|\$|\.)([a-z]+)\s*$").unwrap();    // Range and letter
-            static ref LETTER_REGEX : regex::Regex = Regex::new(r"^([a-z\$]{1})\s*$").unwrap();              // Letter only
+            static ref NUM_REGEX : regex::Regex = Regex::new(&format!(r"^({})\s*$", *ADDRESS)).unwrap();                    // Address only
+            static ref NUM_LETTER_REGEX : regex::Regex = Regex::new(&format!(r"^(%|{})([a-z]+)\s*$", *ADDRESS)).unwrap();     // Address and letter
+            static ref RANGE_LETTER : regex::Regex = Regex::new(&format!(r"^({0}),({0})([a-z]+)\s*$", *ADDRESS)).unwrap();    // Range and letter
+            static ref LETTER_REGEX : regex::Regex = Regex::new(r"^([a-z]{1})\s*$").unwrap();              // Letter only
             static ref LETTER_ARG_REGEX : regex::Regex = Regex::new(r"^([a-z])\s*([^\s]+)\s*$").unwrap(); // Letter and arg
-            static ref SEARCH_REGEX : regex::Regex = Regex::new(r"^[/\?]{1}(.*)[/\?]{1}\n$").unwrap();
+            static ref SEARCH_REGEX : regex::Regex = Regex::new(r"^(?:/((?:[^/\\\n]|\\.)*)/?|\?((?:[^?\\\n]|\\.)*)\??)\s*$").unwrap(); // Search, closing delimiter optional
+            static ref WRITE_REGEX : regex::Regex = Regex::new(&format!(r"^(?:(%)|({0})(?:,({0}))?)?([wW])(t?)\s+(.+?)\s*$", *ADDRESS)).unwrap(); // Write range to a file
+            static ref LINE_MARK_REGEX : regex::Regex = Regex::new(&format!(r"^({})?k([a-z])\s*$", *ADDRESS)).unwrap(); // Mark a line
+            static ref LIST_MATCHES_REGEX : regex::Regex = Regex::new(r"^F([hlu]?)\s*/((?:[^/\\\n]|\\.)*)/?\s*$").unwrap(); // List every match
+            static ref SHELL_REGEX : regex::Regex = Regex::new(r"^(r\s*)?!\s*(.+?)\s*$").unwrap(); // Run or read a shell command
+            static ref GLOBAL_HISTORY_REGEX : regex::Regex = Regex::new(r"^H(?:\s+(.*?))?\s*$").unwrap(); // Global history commands
+            static ref SESSION_REGEX : regex::Regex = Regex::new(r"^S([sl]?)(?:\s+([^\s]+))?\s*$").unwrap(); // Session commands
+            static ref MARK_REGEX : regex::Regex = Regex::new(r"^m\s*([^\s]+)\s*(.*?)\s*$").unwrap(); // Bookmark with optional tags
+            static ref BOOKMARK_REGEX : regex::Regex = Regex::new(r"^K([drei]?)(?:\s+([^\s]+))?(?:\s+([^\s]+))?\s*$").unwrap(); // Bookmark commands
+            static ref GLOBAL_REGEX : regex::Regex = Regex::new(r"^([gv])/((?:[^/\\]|\\.)*)/(.*?)\s*$").unwrap(); // Global commands
+            static ref IDENTITY_REGEX : regex::Regex = Regex::new(r"^I([nar]?)(?:\s+([^\s]+))?(?:\s+([^\s]+))?\s*$").unwrap(); // Identity commands
     }
 
     if resp == "\n" {
         return Ok(ParseResponse::Empty);
     }
 
-    if NUM_REGEX.is_match(&resp) {
+    if GLOBAL_REGEX.is_match(&resp) {
+        if let Some(cmds) = GLOBAL_REGEX.captures(&resp) {
+            if let (Some(cmd), Some(pattern), Some(command)) =
+                (cmds.get(1), cmds.get(2), cmds.get(3))
+            {
+                let command = match command.as_str() {
+                    "" => "p",
+                    command => command,
+                };
+                return Ok(ParseResponse::Global {
+                    pattern: pattern.as_str().replace("\\/", "/"),
+                    invert: cmd.as_str() == "v",
+                    command: command.to_string(),
+                });
+            }
+        }
+    } else if SHELL_REGEX.is_match(&resp) {
+        if let Some(cmds) = SHELL_REGEX.captures(&resp) {
+            if let Some(command) = cmds.get(2) {
+                let command = command.as_str().to_string();
+                if cmds.get(1).is_some() {
+                    return Ok(ParseResponse::ReadCommand(command));
+                }
+                return Ok(ParseResponse::Shell(command));
+            }
+        }
+    } else if WRITE_REGEX.is_match(&resp) {
+        if let Some(cmds) = WRITE_REGEX.captures(&resp) {
+            return parse_write_command(&cmds, buf);
+        }
+    } else if SEARCH_REGEX.is_match(&resp) {
+        if let Some(re) = SEARCH_REGEX.captures(&resp) {
+            if let Some(re) = re.get(1) {
+                return Ok(ParseResponse::SearchForwards(
+                    re.as_str().replace("\\/", "/"),
+                ));
+            }
+            if let Some(re) = re.get(2) {
+                return Ok(ParseResponse::SearchBackwards(
+                    re.as_str().replace("\\?", "?"),
+                ));
+            }
+        }
+    } else if NUM_REGEX.is_match(&resp) {
         if let Some(num) = NUM_REGEX.captures(&resp) {
             if let Some(num) = num.get(1) {
-                return Ok(ParseResponse::JumpToLine(parse_num(
-                    num.as_str(),
-                    buf.lines.len(),
-                    buf.curr_line,
-                )));
+                return Ok(ParseResponse::JumpToLine(parse_num(num.as_str(), buf)?));
+            }
+        }
+    } else if LINE_MARK_REGEX.is_match(&resp) {
+        if let Some(cmds) = LINE_MARK_REGEX.captures(&resp) {
+            if let Some(mark) = cmds.get(2).and_then(|m| m.as_str().chars().next()) {
+                let line = match cmds.get(1) {
+                    Some(num) => parse_num(num.as_str(), buf)?,
+                    None => buf.curr_line,
+                };
+                return Ok(ParseResponse::MarkLine(mark, line));
             }
         }
     } else if NUM_LETTER_REGEX.is_match(&resp) {
@@ -55,7 +177,7 @@ fn parse_response(resp: String, buf: &PageBuf) -> StrResult<ParseResponse> {
                         _ => ParseResponse::Invalid,
                     });
                 }
-                let num = parse_num(num.as_str(), buf.lines.len(), buf.curr_line);
+                let num = parse_num(num.as_str(), buf)?;
                 let cmd = cmd.as_str();
                 return Ok(match cmd {
                     "p" => ParseResponse::Print {
@@ -77,8 +199,8 @@ fn parse_response(resp: String, buf: &PageBuf) -> StrResult<ParseResponse> {
             if let (Some(num_start), Some(num_end), Some(cmd)) =
                 (cmds.get(1), cmds.get(2), cmds.get(3))
             {
-                let num_start = parse_num(num_start.as_str(), buf.lines.len(), buf.curr_line);
-                let mut num_end = parse_num(num_end.as_str(), buf.lines.len(), buf.curr_line);
+                let num_start = parse_num(num_start.as_str(), buf)?;
+                let mut num_end = parse_num(num_end.as_str(), buf)?;
                 if num_end < num_start {
                     num_end = num_start;
                 }
@@ -105,25 +227,36 @@ fn parse_response(resp: String, buf: &PageBuf) -> StrResult<ParseResponse> {
                 return Ok(match cmd {
                     "p" => ParseResponse::Print {
                         use_range: true,
-                        start: buf.curr_line as usize,
-                        stop: buf.curr_line as usize,
+                        start: buf.curr_line,
+                        stop: buf.curr_line,
                     },
                     "n" => ParseResponse::Enumerate {
                         use_range: true,
-                        start: buf.curr_line as usize,
-                        stop: buf.curr_line as usize,
+                        start: buf.curr_line,
+                        stop: buf.curr_line,
                     },
                     "z" => ParseResponse::Page(24),
                     "q" => ParseResponse::Quit,
-                    "$" => ParseResponse::JumpToLine(buf.lines.len()),
                     "b" => ParseResponse::GoBack(1),
                     "f" => ParseResponse::GoForward(1),
                     "h" => ParseResponse::History(-1),
                     "c" => ParseResponse::Clear,
+                    "u" => ParseResponse::ToggleUrls,
+                    "o" => ParseResponse::Outline,
+                    "i" => ParseResponse::Info,
                     _ => ParseResponse::Invalid,
                 });
             }
         }
+    } else if MARK_REGEX.is_match(&resp) {
+        if let Some(cmds) = MARK_REGEX.captures(&resp) {
+            if let (Some(mark), Some(tags), Some(_)) = (cmds.get(1), cmds.get(2), &buf.url) {
+                return Ok(ParseResponse::AddBookmark(
+                    mark.as_str().to_string(),
+                    marks::split_tags(tags.as_str()),
+                ));
+            }
+        }
     } else if LETTER_ARG_REGEX.is_match(&resp) {
         if let Some(cmd) = LETTER_ARG_REGEX.captures(&resp) {
             if let (Some(cmd), Some(arg)) = (cmd.get(1), cmd.get(2)) {
@@ -160,37 +293,64 @@ fn parse_response(resp: String, buf: &PageBuf) -> StrResult<ParseResponse> {
                             Ok(ParseResponse::History(-1))
                         }
                     }
-                    "m" => {
-                        if buf.url.is_some() {
-                            if let Some(c) = arg.chars().next() {
-                                Ok(ParseResponse::AddBookmark(c))
-                            } else {
-                                Ok(ParseResponse::Invalid)
-                            }
-                        } else {
-                            Ok(ParseResponse::Invalid)
-                        }
-                    }
-
-                    "k" => {
-                        if let Some(c) = arg.chars().next() {
-                            Ok(ParseResponse::GoBookmark(c))
+                    "o" => {
+                        if let Ok(n) = arg.parse::<usize>() {
+                            Ok(ParseResponse::JumpToHeading(n))
                         } else {
                             Ok(ParseResponse::Invalid)
                         }
                     }
-
                     _ => Ok(ParseResponse::Invalid),
                 };
             }
         }
-    } else if SEARCH_REGEX.is_match(&resp) {
-        if let Some(re) = SEARCH_REGEX.captures(&resp) {
-            if let Some(re) = re.get(1) {
-                if resp.starts_with('/') {
-                    return Ok(ParseResponse::SearchForwards(re.as_str().to_string()));
-                }
-                return Ok(ParseResponse::SearchBackwards(re.as_str().to_string()));
+    } else if LIST_MATCHES_REGEX.is_match(&resp) {
+        if let Some(cmds) = LIST_MATCHES_REGEX.captures(&resp) {
+            if let (Some(scope), Some(pattern)) = (cmds.get(1), cmds.get(2)) {
+                let scope = match scope.as_str() {
+                    "h" => SearchScope::Headings,
+                    "l" => SearchScope::Links,
+                    "u" => SearchScope::Urls,
+                    _ => SearchScope::Text,
+                };
+                return Ok(ParseResponse::ListMatches(
+                    pattern.as_str().replace("\\/", "/"),
+                    scope,
+                ));
+            }
+        }
+    } else if GLOBAL_HISTORY_REGEX.is_match(&resp) {
+        if let Some(arg) = GLOBAL_HISTORY_REGEX.captures(&resp) {
+            let arg = arg.get(1).map_or("", |m| m.as_str());
+            return Ok(parse_global_history_command(arg));
+        }
+    } else if BOOKMARK_REGEX.is_match(&resp) {
+        if let Some(cmds) = BOOKMARK_REGEX.captures(&resp) {
+            if let Some(cmd) = cmds.get(1) {
+                let name = cmds.get(2).map_or("", |m| m.as_str());
+                let arg = cmds.get(3).map_or("", |m| m.as_str());
+                return Ok(parse_bookmark_command(cmd.as_str(), name, arg));
+            }
+        }
+    } else if SESSION_REGEX.is_match(&resp) {
+        if let Some(cmds) = SESSION_REGEX.captures(&resp) {
+            if let Some(cmd) = cmds.get(1) {
+                let name = cmds.get(2).map(|name| name.as_str());
+                let session = name.unwrap_or(session::DEFAULT).to_string();
+                return Ok(match (cmd.as_str(), name) {
+                    ("", None) => ParseResponse::ListSessions,
+                    ("s", _) => ParseResponse::SaveSession(session),
+                    ("l", _) => ParseResponse::LoadSession(session),
+                    _ => ParseResponse::Invalid,
+                });
+            }
+        }
+    } else if IDENTITY_REGEX.is_match(&resp) {
+        if let Some(cmds) = IDENTITY_REGEX.captures(&resp) {
+            if let Some(cmd) = cmds.get(1) {
+                let name = cmds.get(2).map_or("", |m| m.as_str());
+                let arg = cmds.get(3).map_or("", |m| m.as_str());
+                return parse_identity_command(cmd.as_str(), name, arg, buf);
             }
         }
     }
@@ -198,59 +358,271 @@ fn parse_response(resp: String, buf: &PageBuf) -> StrResult<ParseResponse> {
     Ok(ParseResponse::Invalid)
 }
 
-fn parse_num(num: &str, mut page_length: usize, curr_line: usize) -> usize {
-    if page_length < 1 {
-        page_length = 1;
+// Resolve an ed style address to a line index. An address is a line number,
+// ".", "$", a line mark ('a) or a pattern to search for (/re/ or ?re?),
+// followed by any number of +n or -n offsets. A lone offset is relative to the
+// current line. Offsets are clamped to the buffer.
+fn parse_num(num: &str, buf: &PageBuf) -> ReiResult<usize> {
+    lazy_static! {
+        static ref PARTS: regex::Regex =
+            Regex::new(&format!(r"^({})?((?:[\+-][0-9]*)*)$", ADDRESS_BASE)).unwrap();
+        static ref OFFSET: regex::Regex = Regex::new(r"([\+-])([0-9]*)").unwrap();
     }
-    if num == "$" {
-        page_length - 1
-    } else if num == "." {
-        curr_line
-    } else if let Some(num) = num.strip_prefix('+') {
-        if let Ok(offset) = num.parse::<usize>() {
-            if curr_line + offset <= page_length - 1 {
-                curr_line + offset
-            } else {
-                page_length - 1
-            }
-        } else {
-            curr_line
+    let last = buf.lines.len().max(1) as isize - 1;
+    let parts = PARTS
+        .captures(num)
+        .ok_or_else(|| Error::Command(format!("Invalid address {}.", num)))?;
+    let base = parts.get(1).map(|m| m.as_str()).unwrap_or(".");
+    let mut line = if base == "." {
+        buf.curr_line as isize
+    } else if base == "$" {
+        last
+    } else if let Some(mark) = base.strip_prefix('\'') {
+        let mark = mark.chars().next().unwrap_or(' ');
+        *buf.line_marks
+            .get(&mark)
+            .ok_or_else(|| Error::Command(format!("No line marked {}.", mark)))? as isize
+    } else if base.starts_with('/') || base.starts_with('?') {
+        let mut pattern = base[1..base.len() - 1].replace(&format!("\\{}", &base[..1]), &base[..1]);
+        if pattern.is_empty() {
+            pattern = buf
+                .last_pattern
+                .clone()
+                .ok_or_else(|| Error::command("No previous pattern."))?;
         }
-    } else if let Some(num) = num.strip_prefix('-') {
-        if let Ok(offset) = num.parse::<usize>() {
-            if curr_line as isize - offset as isize >= 0 {
-                curr_line - offset
-            } else {
-                0
-            }
+        let re = Regex::new(&pattern).map_err(Error::pattern)?;
+        buf.search(&re, base.starts_with('/'))
+            .ok_or_else(|| Error::Command(format!("No match for {}.", pattern)))? as isize
+    } else {
+        base.parse::<isize>()
+            .map_err(|_| Error::Command(format!("Invalid address {}.", num)))?
+            - 1
+    };
+
+    let offsets = parts.get(2).map(|m| m.as_str()).unwrap_or("");
+    for offset in OFFSET.captures_iter(offsets) {
+        let n = match offset.get(2).map(|m| m.as_str()) {
+            Some("") | None => 1,
+            Some(n) => n
+                .parse::<isize>()
+                .map_err(|_| Error::Command(format!("Invalid address {}.", num)))?,
+        };
+        if &offset[1] == "+" {
+            line = line.saturating_add(n);
         } else {
-            curr_line
+            line = line.saturating_sub(n);
         }
-    } else if let Ok(num) = num.parse::<usize>() {
-        num - 1
-    } else {
-        0
     }
+    if !offsets.is_empty() {
+        line = line.min(last);
+    }
+    Ok(line.max(0) as usize)
 }
 
-fn parse_go_command(url: &str) -> StrResult<ParseResponse> {
+fn parse_go_command(url: &str) -> ReiResult<ParseResponse> {
     let mut new_url = "gemini://".to_string();
     if !url.starts_with("gemini://") {
         new_url.push_str(url);
-        if let Ok(url) = url::Url::parse(&new_url) {
-            return Ok(ParseResponse::GoUrl(url));
-        } else {
-            return Err("Unable to parse URL.");
-        }
-    } else if let Ok(url) = url::Url::parse(url) {
-        return Ok(ParseResponse::GoUrl(url));
+    } else {
+        new_url = url.to_string();
+    }
+    match url::Url::parse(&new_url) {
+        Ok(url) => Ok(ParseResponse::GoUrl(url)),
+        Err(e) => Err(Error::url(&new_url, e)),
     }
-    Err("Unable to parse URL.")
 }
 
-fn parse_link_command(id: &str) -> StrResult<ParseResponse> {
+fn parse_link_command(id: &str) -> ReiResult<ParseResponse> {
     if let Ok(id) = id.parse::<usize>() {
         return Ok(ParseResponse::FollowLink(id));
     }
-    Err("Invalid link id.")
+    Err(Error::Command(format!("Invalid link id {}.", id)))
+}
+
+// Build a write command from its address range, letter, format and file.
+// Without an address the whole buffer is written.
+fn parse_write_command(cmds: &regex::Captures, buf: &PageBuf) -> ReiResult<ParseResponse> {
+    let len = buf.lines.len();
+    let (start, stop) = match (cmds.get(2), cmds.get(3)) {
+        (Some(start), stop) => {
+            let start = parse_num(start.as_str(), buf)?;
+            let stop = match stop {
+                Some(stop) => parse_num(stop.as_str(), buf)?,
+                None => start,
+            };
+            (start, stop.max(start))
+        }
+        _ => (0, len.saturating_sub(1)),
+    };
+    Ok(match (cmds.get(4), cmds.get(5), cmds.get(6)) {
+        (Some(cmd), Some(format), Some(path)) => ParseResponse::Write {
+            start,
+            stop,
+            path: path.as_str().to_string(),
+            append: cmd.as_str() == "W",
+            rendered: format.as_str() == "t",
+        },
+        _ => ParseResponse::Invalid,
+    })
+}
+
+// A visit number to revisit, otherwise a pattern to search for, optionally
+// wrapped in slashes.
+fn parse_global_history_command(arg: &str) -> ParseResponse {
+    if let Ok(n) = arg.parse::<usize>() {
+        return ParseResponse::GoGlobalHistory(n);
+    }
+    let pattern = match arg.strip_prefix('/') {
+        Some(pattern) => pattern.strip_suffix('/').unwrap_or(pattern),
+        None => arg,
+    };
+    ParseResponse::SearchGlobalHistory(pattern.to_string())
+}
+
+// K lists bookmarks and K <name> opens one. Bookmarks are added with m <name>.
+// Lowercase k is left to ed's line marks.
+fn parse_bookmark_command(cmd: &str, name: &str, arg: &str) -> ParseResponse {
+    match (cmd, name, arg) {
+        ("", "", "") => ParseResponse::ListBookmarks,
+        ("", name, "") => ParseResponse::GoBookmark(name.to_string()),
+        ("d", name, "") if !name.is_empty() => ParseResponse::DeleteBookmark(name.to_string()),
+        ("e", path, "") if !path.is_empty() => ParseResponse::ExportBookmarks(path.to_string()),
+        ("i", "", "") => ParseResponse::ImportBookmarks(None),
+        ("i", path, "") => ParseResponse::ImportBookmarks(Some(path.to_string())),
+        ("r", name, new_name) if !name.is_empty() && !new_name.is_empty() => {
+            ParseResponse::RenameBookmark(name.to_string(), new_name.to_string())
+        }
+        _ => ParseResponse::Invalid,
+    }
+}
+
+fn parse_identity_command(
+    cmd: &str,
+    name: &str,
+    arg: &str,
+    buf: &PageBuf,
+) -> ReiResult<ParseResponse> {
+    if cmd.is_empty() && name.is_empty() {
+        return Ok(ParseResponse::ListIdentities);
+    }
+    if name.is_empty() {
+        return Ok(ParseResponse::Invalid);
+    }
+    match cmd {
+        "n" if arg.is_empty() => Ok(ParseResponse::NewIdentity(name.to_string())),
+        "r" if arg.is_empty() => Ok(ParseResponse::RevokeIdentity(name.to_string())),
+        "a" => {
+            if arg.is_empty() {
+                if let Some(url) = &buf.url {
+                    return Ok(ParseResponse::AttachIdentity(name.to_string(), url.clone()));
+                }
+                return Err(Error::command("No page loaded to attach to."));
+            }
+            match parse_go_command(arg)? {
+                ParseResponse::GoUrl(url) => {
+                    Ok(ParseResponse::AttachIdentity(name.to_string(), url))
+                }
+                _ => Ok(ParseResponse::Invalid),
+            }
+        }
+        _ => Ok(ParseResponse::Invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A buffer of plain lines with the cursor on curr_line.
+    fn buffer(lines: &[&str], curr_line: usize) -> PageBuf {
+        PageBuf {
+            lines: lines
+                .iter()
+                .map(|line| GemTextLine::Line(line.to_string()))
+                .collect(),
+            curr_line,
+            url: None,
+            redirects: Vec::new(),
+            lang: None,
+            info: None,
+            line_marks: HashMap::new(),
+            last_pattern: None,
+        }
+    }
+
+    const LINES: [&str; 10] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    ];
+
+    #[test]
+    fn numbers_dot_and_dollar() {
+        let buf = buffer(&LINES, 4);
+        assert_eq!(parse_num("1", &buf).unwrap(), 0);
+        assert_eq!(parse_num("10", &buf).unwrap(), 9);
+        assert_eq!(parse_num(".", &buf).unwrap(), 4);
+        assert_eq!(parse_num("$", &buf).unwrap(), 9);
+    }
+
+    #[test]
+    fn line_marks() {
+        let mut buf = buffer(&LINES, 0);
+        buf.line_marks.insert('a', 6);
+        assert_eq!(parse_num("'a", &buf).unwrap(), 6);
+        assert_eq!(parse_num("'a+1", &buf).unwrap(), 7);
+        assert!(parse_num("'b", &buf).is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        let buf = buffer(&LINES, 4);
+        assert_eq!(parse_num("/e/", &buf).unwrap(), 5);
+        assert_eq!(parse_num("?e?", &buf).unwrap(), 3);
+        // Searches wrap around the buffer.
+        assert_eq!(parse_num("/zero/", &buf).unwrap(), 0);
+        assert_eq!(parse_num("?nine?", &buf).unwrap(), 9);
+        assert_eq!(parse_num("/t/-1", &buf).unwrap(), 7);
+        assert!(parse_num("/missing/", &buf).is_err());
+    }
+
+    #[test]
+    fn empty_pattern_repeats_the_last_one() {
+        let mut buf = buffer(&LINES, 0);
+        assert!(parse_num("//", &buf).is_err());
+        buf.last_pattern = Some("ee".to_string());
+        assert_eq!(parse_num("//", &buf).unwrap(), 3);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let buf = buffer(&LINES, 4);
+        assert_eq!(parse_num(".+3", &buf).unwrap(), 7);
+        assert_eq!(parse_num("$-5", &buf).unwrap(), 4);
+        assert_eq!(parse_num("2+2-1", &buf).unwrap(), 2);
+        assert_eq!(parse_num(".++", &buf).unwrap(), 6);
+    }
+
+    #[test]
+    fn lone_offsets_are_relative_to_the_current_line() {
+        let buf = buffer(&LINES, 4);
+        assert_eq!(parse_num("-", &buf).unwrap(), 3);
+        assert_eq!(parse_num("+", &buf).unwrap(), 5);
+        assert_eq!(parse_num("-2", &buf).unwrap(), 2);
+        assert_eq!(parse_num("+3", &buf).unwrap(), 7);
+    }
+
+    #[test]
+    fn offsets_are_clamped_to_the_buffer() {
+        let buf = buffer(&LINES, 4);
+        assert_eq!(parse_num("$+5", &buf).unwrap(), 9);
+        assert_eq!(parse_num(".-100", &buf).unwrap(), 0);
+        assert_eq!(parse_num("1-", &buf).unwrap(), 0);
+        assert_eq!(parse_num("+99999999999999999", &buf).unwrap(), 9);
+    }
+
+    #[test]
+    fn overflowing_numbers_are_rejected() {
+        let buf = buffer(&LINES, 4);
+        assert!(parse_num("99999999999999999999", &buf).is_err());
+        assert!(parse_num(".+99999999999999999999", &buf).is_err());
+    }
 }
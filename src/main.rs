@@ -1,33 +1,105 @@
+use error::{Error, ReiResult};
 use gemini_fetch::*;
 use lazy_static::*;
 use regex::Regex;
 use std::collections::HashMap;
 use std::{convert::TryInto, io::Write};
+mod config;
+mod download;
+mod error;
 mod exec;
+mod fetch;
+mod history;
+mod identity;
 mod interface;
 mod marks;
+mod mime;
+mod render;
+mod session;
+mod shell;
+mod tofu;
 
-pub type Bookmarks = HashMap<char, String>;
-pub type StrResult<T> = Result<T, &'static str>;
+pub type Bookmarks = HashMap<String, marks::Bookmark>;
 /// Structures for representing the page buffer and history.
-// TODO: Add more types!
 pub enum GemTextLine {
     H1(String),
     H2(String),
     H3(String),
-    Link(usize, String, url::Url),
+    Link(usize, Option<String>, url::Url), // Link id, label and target.
     Line(String),
+    Quote(String),
+    ListItem(String),
+    Preformatted(usize, Option<String>, String), // Block id, alt text and line.
+}
+
+impl GemTextLine {
+    // The text a line is searched by. Unlabelled links use their url.
+    pub fn text(&self) -> &str {
+        match self {
+            GemTextLine::H1(text)
+            | GemTextLine::H2(text)
+            | GemTextLine::H3(text)
+            | GemTextLine::Line(text)
+            | GemTextLine::Quote(text)
+            | GemTextLine::ListItem(text)
+            | GemTextLine::Preformatted(_, _, text) => text,
+            GemTextLine::Link(_, Some(label), _) => label,
+            GemTextLine::Link(_, None, url) => url.as_str(),
+        }
+    }
 }
 pub struct PageBuf {
     //page: Option<gemini_fetch::Page>, // The raw page response.
     lines: Vec<GemTextLine>, // The parsed lines for display.
     curr_line: usize,
     url: Option<url::Url>,
+    redirects: Vec<url::Url>, // Redirects followed to reach url.
+    lang: Option<String>,     // Language of the page from its MIME type.
+    info: Option<PageInfo>,
+    line_marks: HashMap<char, usize>, // Lines marked with k, by mark.
+    last_pattern: Option<String>,     // Last pattern searched for.
+}
+
+impl PageBuf {
+    // The page title, taken from the first level one heading.
+    pub fn title(&self) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            GemTextLine::H1(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    // The next line after (or before) the current one matching re, wrapping
+    // around the buffer and ending with the current line itself.
+    pub fn search(&self, re: &Regex, forwards: bool) -> Option<usize> {
+        let len = self.lines.len();
+        (1..=len)
+            .map(|step| {
+                if forwards {
+                    (self.curr_line + step) % len
+                } else {
+                    (self.curr_line + len * step - step) % len
+                }
+            })
+            .find(|i| re.is_match(self.lines[*i].text()))
+    }
+}
+
+/// How the page in the buffer was fetched.
+pub struct PageInfo {
+    status: u8,
+    meta: String,
+    size: usize, // Length of the body in bytes.
+    fetched: chrono::DateTime<chrono::Local>,
+    elapsed: std::time::Duration,
+    cert: fetch::ServerCert,
+    trust: tofu::Trust,
 }
 
 pub struct History {
     entry: Vec<url::Url>,
     curr_entry: usize,
+    global: history::GlobalHistory, // Every visit, kept across sessions.
 }
 
 impl History {
@@ -45,13 +117,28 @@ impl History {
     }
 }
 
+/// The parts of each line a listing search looks at.
+pub enum SearchScope {
+    Text,     // The displayed text of every line.
+    Headings, // Heading text only.
+    Links,    // Link labels only.
+    Urls,     // Link targets only.
+}
+
 /// Enum representing all of the available commands and their associated data.
 pub enum ParseResponse {
     GoUrl(url::Url),
     SearchBackwards(String),
     SearchForwards(String),
-    FollowLink(usize), // Index of link on page.
+    ListMatches(String, SearchScope), // Pattern and what to match it against.
+    FollowLink(usize),                // Index of link on page.
+    Global {
+        pattern: String,
+        invert: bool,    // Run on lines that do not match instead.
+        command: String, // Run with each line as the current line.
+    },
     JumpToLine(usize),
+    MarkLine(char, usize), // Mark and line, from [addr]kx. Addressed afterwards as 'x.
     GoBack(usize),
     GoForward(usize),
     Print {
@@ -64,11 +151,38 @@ pub enum ParseResponse {
         start: usize,
         stop: usize,
     },
-    Page(usize),    // Number of lines to page.
-    History(isize), // Number of entries to show (-1 means show all)
+    Write {
+        start: usize,
+        stop: usize,
+        path: String,
+        append: bool,   // Add to the end of the file instead of replacing it.
+        rendered: bool, // Write the text as displayed instead of gemtext.
+    },
+    Shell(String),               // Command to run.
+    ReadCommand(String),         // Command whose output replaces the buffer.
+    Page(usize),                 // Number of lines to page.
+    History(isize),              // Number of entries to show (-1 means show all)
+    SearchGlobalHistory(String), // Pattern to match, empty to list every visit.
+    GoGlobalHistory(usize),      // Number of the visit in the global history.
     Clear,
-    AddBookmark(char),
-    GoBookmark(char),
+    Info,                             // Describe the current page.
+    Outline,                          // List the headings on the page.
+    JumpToHeading(usize),             // Number of the heading in the outline.
+    ToggleUrls,                       // Show or hide link urls next to their labels.
+    AddBookmark(String, Vec<String>), // Name and tags.
+    GoBookmark(String),               // Name, from K <name>.
+    ListBookmarks,
+    DeleteBookmark(String),
+    RenameBookmark(String, String),  // Old and new name.
+    ExportBookmarks(String),         // File to write.
+    ImportBookmarks(Option<String>), // File to read, or the current page if None.
+    ListIdentities,
+    NewIdentity(String),
+    AttachIdentity(String, url::Url), // Identity name and url prefix.
+    RevokeIdentity(String),
+    ListSessions,
+    SaveSession(String),
+    LoadSession(String),
     Invalid,
     Empty,
     Quit,
@@ -83,25 +197,44 @@ async fn main() {
         lines: Vec::new(),
         curr_line: 0,
         url: None,
+        redirects: Vec::new(),
+        lang: None,
+        info: None,
+        line_marks: HashMap::new(),
+        last_pattern: None,
     };
     let mut hist = History {
         entry: Vec::new(),
         curr_entry: 0,
+        global: history::GlobalHistory::load(),
     };
-    let mut marks = marks::load_marks();
+    let mut marks = marks::load_marks().unwrap_or_else(|msg| {
+        println!("{}", msg);
+        HashMap::new()
+    });
+    let mut config = config::load();
+    if config.save_session && session::exists(session::DEFAULT) {
+        if let Err(msg) = session::restore(session::DEFAULT, &mut buf, &mut hist, &config).await {
+            println!("{}", msg);
+        }
+    }
     while cont {
         match interface::prompt(&buf) {
             Ok(p) => {
-                if exec::execute_command(p, &mut buf, &mut hist, &mut marks).await {
+                if exec::execute_command(p, &mut buf, &mut hist, &mut marks, &mut config).await {
                     continue;
                 }
             }
-            Err(msg) => println!("{}", msg),
+            // A command that fails to parse should not end the session.
+            Err(msg) => {
+                println!("{}", msg);
+                continue;
+            }
         }
         cont = false;
     }
-    if !marks.is_empty() {
-        if let Err(msg) = marks::save_bookmarks(&marks) {
+    if config.save_session {
+        if let Err(msg) = session::save(session::DEFAULT, &buf, &hist) {
             println!("{}", msg);
         }
     }
@@ -2,76 +2,385 @@ use crate::exec::*;
 use crate::*;
 use std::fs::OpenOptions;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
-pub fn load_marks() -> Bookmarks {
-    let mut map = HashMap::new();
-    if let Some(mut marks_dir) = dirs::home_dir() {
-        marks_dir.push(".reimarks");
-        if let Ok(mut marks_file) = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(marks_dir.as_os_str())
-        {
-            let mut buf = String::new();
-            if marks_file.read_to_string(&mut buf).is_err() {
-                return map;
-            }
-            for line in buf.split('\n') {
-                let mut split = line.split_whitespace();
-                let k = split.next();
-                let v = split.next();
-                if let (Some(k), Some(v)) = (k, v) {
-                    if let Some(k) = k.chars().next() {
-                        map.insert(k, v.to_string());
-                    }
-                }
-            }
-        }
-    }
-    map
+/// Bookmarks.
+// Stored in <config dir>/rei/bookmarks, one "mark<TAB>url<TAB>created<TAB>tags<TAB>title"
+// entry per line, with tags separated by commas. The file is written to a
+// temporary file first and renamed over the old one. Bookmarks in the old
+// ~/.reimarks "c url" format are migrated the first time they are loaded.
+pub struct Bookmark {
+    pub url: String,
+    pub title: String,
+    pub created: i64, // Unix timestamp, 0 if unknown.
+    pub tags: Vec<String>,
 }
 
-pub fn add_bookmark(mark: char, buf: &mut PageBuf, marks: &mut Bookmarks) -> StrResult<()> {
-    if let Some(url) = &buf.url {
-        marks.insert(mark, url.as_str().to_string());
-        return Ok(());
+// Heading for exported bookmarks without tags.
+const UNTAGGED: &str = "Untagged";
+
+pub fn load_marks() -> ReiResult<Bookmarks> {
+    let path = marks_path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(data) => Ok(parse_marks(&data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => migrate_marks(),
+        Err(e) => Err(Error::fs("Unable to read", &path, e)),
     }
-    Err("No page loaded to mark.")
+}
+
+pub fn add_bookmark(
+    mark: &str,
+    tags: Vec<String>,
+    buf: &mut PageBuf,
+    marks: &mut Bookmarks,
+) -> ReiResult<()> {
+    let url = buf
+        .url
+        .as_ref()
+        .ok_or_else(|| Error::Bookmark("No page loaded to mark.".to_string()))?;
+    marks.insert(
+        mark.to_string(),
+        Bookmark {
+            url: url.as_str().to_string(),
+            title: buf.title().unwrap_or("").to_string(),
+            created: chrono::Utc::now().timestamp(),
+            tags,
+        },
+    );
+    save_bookmarks(marks)
 }
 
 pub async fn go_to_bookmark(
-    mark: char,
+    mark: &str,
     buf: &mut PageBuf,
     hist: &mut History,
     marks: &Bookmarks,
-) -> StrResult<()> {
-    if let Some((_, url)) = marks.get_key_value(&mark) {
-        if let Ok(url) = url::Url::parse(url) {
-            if let Ok(page) = go_url(&url).await {
-                if page.body.is_some() {
-                    let _ = load_page(&page, buf, hist, true);
-                    buf.url = Some(url);
-                    println!("{}", page.body.unwrap().len());
-                    return Ok(());
+    config: &config::Config,
+) -> ReiResult<()> {
+    let mark = marks
+        .get(mark)
+        .ok_or_else(|| Error::Bookmark(format!("No bookmark {}.", mark)))?;
+    let url = url::Url::parse(&mark.url).map_err(|e| Error::url(&mark.url, e))?;
+    visit(&url, buf, hist, true, config).await
+}
+
+// Print every bookmark with its title, url and tags.
+pub fn list_bookmarks(marks: &Bookmarks) {
+    let mut keys: Vec<&String> = marks.keys().collect();
+    keys.sort();
+    for k in keys {
+        let mark = &marks[k];
+        let title = if mark.title.is_empty() {
+            &mark.url
+        } else {
+            &mark.title
+        };
+        print!("{}\t{}", k, title);
+        if mark.created > 0 {
+            print!(" ({})", render::format_time(mark.created));
+        }
+        println!();
+        if !mark.title.is_empty() {
+            println!("\t{}", mark.url);
+        }
+        if !mark.tags.is_empty() {
+            println!("\t{}", mark.tags.join(", "));
+        }
+    }
+}
+
+pub fn delete_bookmark(mark: &str, marks: &mut Bookmarks) -> ReiResult<()> {
+    marks
+        .remove(mark)
+        .ok_or_else(|| Error::Bookmark(format!("No bookmark {}.", mark)))?;
+    save_bookmarks(marks)
+}
+
+pub fn rename_bookmark(mark: &str, name: &str, marks: &mut Bookmarks) -> ReiResult<()> {
+    if marks.contains_key(name) {
+        return Err(Error::Bookmark(format!(
+            "Bookmark {} already exists.",
+            name
+        )));
+    }
+    let bookmark = marks
+        .remove(mark)
+        .ok_or_else(|| Error::Bookmark(format!("No bookmark {}.", mark)))?;
+    marks.insert(name.to_string(), bookmark);
+    save_bookmarks(marks)
+}
+
+// Write every bookmark to a gemtext file, under a heading for each of its tags.
+pub fn export_bookmarks(path: &str, marks: &Bookmarks) -> ReiResult<()> {
+    if Path::new(path).exists() && !interface::prompt_yes_no(&format!("Overwrite {}?", path)) {
+        return Err(Error::command("Not exported."));
+    }
+    let mut tags: Vec<&str> = marks
+        .values()
+        .flat_map(|mark| mark.tags.iter().map(|tag| tag.as_str()))
+        .collect();
+    tags.sort_unstable();
+    tags.dedup();
+    let mut keys: Vec<&String> = marks.keys().collect();
+    keys.sort();
+
+    let mut out = "# Bookmarks\n".to_string();
+    let mut section = |heading: &str, keys: Vec<&&String>| {
+        if keys.is_empty() {
+            return;
+        }
+        out.push_str(&format!("\n## {}\n", heading));
+        for k in keys {
+            let mark = &marks[*k];
+            let label = if mark.title.is_empty() {
+                k.as_str()
+            } else {
+                mark.title.as_str()
+            };
+            out.push_str(&format!("=> {} {}\n", mark.url, clean(label)));
+        }
+    };
+    for tag in tags {
+        section(
+            tag,
+            keys.iter()
+                .filter(|k| marks[**k].tags.iter().any(|t| t == tag))
+                .collect(),
+        );
+    }
+    section(
+        UNTAGGED,
+        keys.iter().filter(|k| marks[**k].tags.is_empty()).collect(),
+    );
+    write_atomic(Path::new(path), out.as_bytes())?;
+    println!("Exported {} bookmarks to {}", marks.len(), path);
+    Ok(())
+}
+
+// Add the gemini links in lines as bookmarks, skipping urls already marked.
+// Links are tagged with the level two heading they appear under and named
+// after their label. A link repeated under another heading gains that tag.
+pub fn import_bookmarks(lines: &[GemTextLine], marks: &mut Bookmarks) -> ReiResult<()> {
+    let mut tag = None;
+    let mut imported: Vec<String> = Vec::new();
+    let (mut duplicates, mut skipped) = (0, 0);
+    for line in lines {
+        let (label, url) = match line {
+            GemTextLine::H2(text) => {
+                tag = match text.as_str() {
+                    UNTAGGED => None,
+                    text => Some(split_tags(text).join("-")),
+                };
+                continue;
+            }
+            GemTextLine::Link(_, label, url) => (label, url),
+            _ => continue,
+        };
+        if url.scheme() != "gemini" {
+            skipped += 1;
+            continue;
+        }
+        if let Some(name) = imported
+            .iter()
+            .find(|name| marks[*name].url == url.as_str())
+        {
+            if let (Some(mark), Some(tag)) = (marks.get_mut(name), &tag) {
+                if !mark.tags.contains(tag) {
+                    mark.tags.push(tag.clone());
                 }
             }
+            continue;
         }
+        if marks.values().any(|mark| mark.url == url.as_str()) {
+            duplicates += 1;
+            continue;
+        }
+        let title = label.clone().unwrap_or_default();
+        let name = unique_name(&title, url, marks);
+        marks.insert(
+            name.clone(),
+            Bookmark {
+                url: url.as_str().to_string(),
+                title,
+                created: chrono::Utc::now().timestamp(),
+                tags: tag.iter().cloned().collect(),
+            },
+        );
+        imported.push(name);
+    }
+    if !imported.is_empty() {
+        save_bookmarks(marks)?;
     }
-    Err("Unable to load bookmark.")
+    println!(
+        "Imported {} bookmarks, skipped {} duplicates and {} non-gemini links",
+        imported.len(),
+        duplicates,
+        skipped
+    );
+    Ok(())
 }
 
-pub fn save_bookmarks(marks: &Bookmarks) -> StrResult<()> {
-    if let Some(mut marks_dir) = dirs::home_dir() {
-        marks_dir.push(".reimarks");
-        if let Ok(mut marks_file) = OpenOptions::new().write(true).open(marks_dir.as_os_str()) {
-            for (k, v) in marks {
-                if marks_file.write_fmt(format_args!("{} {}\n", k, v)).is_ok() {
-                    println!("Saving bookmark {}: {}", k, v);
-                }
+// A bookmark name made from a label (or the url's host), numbered if taken.
+fn unique_name(label: &str, url: &url::Url, marks: &Bookmarks) -> String {
+    let source = if label.is_empty() {
+        url.host_str().unwrap_or("link")
+    } else {
+        label
+    };
+    let mut base = String::new();
+    for c in source.to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            base.push(c);
+        } else if !base.is_empty() && !base.ends_with('-') {
+            base.push('-');
+        }
+    }
+    let base = match base.trim_end_matches('-') {
+        "" => "link".to_string(),
+        base => base.to_string(),
+    };
+    let mut name = base.clone();
+    let mut n = 1;
+    while marks.contains_key(&name) {
+        n += 1;
+        name = format!("{}-{}", base, n);
+    }
+    name
+}
+
+pub fn save_bookmarks(marks: &Bookmarks) -> ReiResult<()> {
+    let path = marks_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::fs("Unable to create", dir, e))?;
+    }
+    let mut keys: Vec<&String> = marks.keys().collect();
+    keys.sort();
+    let mut out = String::new();
+    for k in keys {
+        let mark = &marks[k];
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            k,
+            mark.url,
+            mark.created,
+            mark.tags.join(","),
+            clean(&mark.title)
+        ));
+    }
+    // Lines that could not be parsed are kept as they are for the user to fix.
+    if let Ok(old) = std::fs::read_to_string(&path) {
+        for line in old
+            .lines()
+            .filter(|line| !line.trim().is_empty() && parse_line(line).is_none())
+        {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    write_atomic(&path, out.as_bytes())
+}
+
+fn parse_marks(data: &str) -> Bookmarks {
+    let mut map = HashMap::new();
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        match parse_line(line) {
+            Some((k, mark)) => {
+                map.insert(k, mark);
             }
-            return Ok(());
+            None => println!("Skipping bookmark line (left in the file): {}", line),
+        }
+    }
+    map
+}
+
+fn parse_line(line: &str) -> Option<(String, Bookmark)> {
+    let fields: Vec<&str> = line.splitn(5, '\t').collect();
+    match fields.as_slice() {
+        [k, url, created, tags, title] if !k.is_empty() => Some((
+            k.to_string(),
+            Bookmark {
+                url: url.to_string(),
+                title: title.to_string(),
+                created: created.parse().unwrap_or(0),
+                tags: split_tags(tags),
+            },
+        )),
+        _ => None,
+    }
+}
+
+// Read bookmarks from the old ~/.reimarks file and save them in the new format.
+fn migrate_marks() -> ReiResult<Bookmarks> {
+    let mut map = HashMap::new();
+    let old_path = match dirs::home_dir() {
+        Some(home) => home.join(".reimarks"),
+        None => return Ok(map),
+    };
+    let mut data = String::new();
+    match OpenOptions::new().read(true).open(&old_path) {
+        Ok(mut file) => file
+            .read_to_string(&mut data)
+            .map_err(|e| Error::fs("Unable to read", &old_path, e))?,
+        Err(_) => return Ok(map),
+    };
+    for line in data.lines() {
+        let mut split = line.trim().splitn(2, ' ');
+        if let (Some(k), Some(url)) = (split.next(), split.next()) {
+            map.insert(
+                k.to_string(),
+                Bookmark {
+                    url: url.trim().to_string(),
+                    title: String::new(),
+                    created: 0,
+                    tags: Vec::new(),
+                },
+            );
         }
-        return Err("Unable to write to bookmarks file.");
     }
-    Err("Unable to find home directory.")
+    if !map.is_empty() {
+        save_bookmarks(&map)?;
+        println!(
+            "Migrated {} bookmarks from {}",
+            map.len(),
+            old_path.display()
+        );
+    }
+    Ok(map)
+}
+
+pub fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect()
+}
+
+// Tabs and newlines would break the file format.
+fn clean(text: &str) -> String {
+    text.replace(&['\t', '\n', '\r'][..], " ")
+}
+
+// Write data next to path under the whole file name plus ".tmp", then rename it
+// over path.
+fn write_atomic(path: &Path, data: &[u8]) -> ReiResult<()> {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp)
+        .map_err(|e| Error::fs("Unable to open", &tmp, e))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| Error::fs("Unable to write to", &tmp, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| Error::fs("Unable to replace", path, e))
+}
+
+fn marks_path() -> ReiResult<PathBuf> {
+    let mut path = dirs::config_dir().ok_or(Error::NoConfigDir)?;
+    path.push("rei");
+    path.push("bookmarks");
+    Ok(path)
 }
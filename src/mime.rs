@@ -0,0 +1,86 @@
+use crate::*;
+
+/// The MIME type sent in the meta of a success response.
+// An empty meta means "text/gemini; charset=utf-8".
+pub struct Mime {
+    pub essence: String,         // Lowercased type/subtype, e.g. text/gemini.
+    pub charset: Option<String>, // Lowercased charset parameter.
+    pub lang: Option<String>,    // Language tags from the lang parameter.
+}
+
+impl Mime {
+    pub fn parse(meta: &str) -> Mime {
+        let mut parts = meta.split(';');
+        let essence = match parts.next().unwrap_or("").trim() {
+            "" => "text/gemini".to_string(),
+            essence => essence.to_ascii_lowercase(),
+        };
+        let mut mime = Mime {
+            essence,
+            charset: None,
+            lang: None,
+        };
+        for param in parts {
+            let mut split = param.splitn(2, '=');
+            if let (Some(key), Some(value)) = (split.next(), split.next()) {
+                let value = value.trim().trim_matches('"');
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "charset" => mime.charset = Some(value.to_ascii_lowercase()),
+                    "lang" if !value.is_empty() => mime.lang = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        mime
+    }
+
+    pub fn is_gemtext(&self) -> bool {
+        self.essence == "text/gemini"
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.essence.starts_with("text/")
+    }
+
+    // The usual file extension for this type, if it is a common one.
+    pub fn extension(&self) -> Option<&'static str> {
+        Some(match self.essence.as_str() {
+            "text/gemini" => "gmi",
+            "text/plain" => "txt",
+            "text/html" => "html",
+            "text/markdown" => "md",
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/svg+xml" => "svg",
+            "audio/mpeg" => "mp3",
+            "audio/ogg" => "ogg",
+            "audio/flac" => "flac",
+            "video/mp4" => "mp4",
+            "video/webm" => "webm",
+            "application/pdf" => "pdf",
+            "application/zip" => "zip",
+            "application/gzip" => "gz",
+            "application/epub+zip" => "epub",
+            "application/json" => "json",
+            _ => return None,
+        })
+    }
+
+    // Decode a body in this type's charset, UTF-8 if unset. A byte order mark
+    // takes precedence over the charset.
+    pub fn decode(&self, data: &[u8]) -> ReiResult<String> {
+        let label = self.charset.as_deref().unwrap_or("utf-8");
+        let encoding = encoding_rs::Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| Error::Protocol(format!("Unsupported charset {}.", label)))?;
+        let (text, used, had_errors) = encoding.decode(data);
+        if had_errors {
+            return Err(Error::Protocol(format!(
+                "Response is not valid {}.",
+                used.name()
+            )));
+        }
+        Ok(text.into_owned())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::*;
+
+/// Turning buffer lines into terminal rows.
+// Text, quotes, list items, headings and link labels are word wrapped to the
+// given width with hanging indents. Preformatted lines are never wrapped.
+pub fn render_line(line: &GemTextLine, config: &config::Config, width: usize) -> Vec<String> {
+    match line {
+        GemTextLine::H1(text) => wrap(text, width, "# ", "  "),
+        GemTextLine::H2(text) => wrap(text, width, "## ", "   "),
+        GemTextLine::H3(text) => wrap(text, width, "### ", "    "),
+        GemTextLine::Line(text) => wrap(text, width, "", ""),
+        GemTextLine::Quote(text) => wrap(text, width, "> ", "> "),
+        GemTextLine::ListItem(text) => wrap(text, width, "  • ", "    "),
+        GemTextLine::Link(id, Some(label), url) => {
+            let prefix = format!("[{}] ", id);
+            let indent = " ".repeat(prefix.chars().count());
+            let text = if config.show_urls {
+                format!("{} => {}", label, url)
+            } else {
+                label.to_string()
+            };
+            wrap(&text, width, &prefix, &indent)
+        }
+        GemTextLine::Link(id, None, url) => vec![format!("[{}] => {}", id, url)],
+        GemTextLine::Preformatted(_, _, text) => vec![text.to_string()],
+    }
+}
+
+// The fence announcing the preformatted block starting at line i, with its alt
+// text if it has any. None if line i does not start a block.
+pub fn block_alt(lines: &[GemTextLine], i: usize) -> Option<String> {
+    if let Some(GemTextLine::Preformatted(block, alt, _)) = lines.get(i) {
+        let starts_block = i == 0
+            || !matches!(&lines[i - 1], GemTextLine::Preformatted(prev, _, _) if prev == block);
+        if starts_block {
+            return Some(match alt {
+                Some(alt) => format!("``` {}", alt),
+                None => "```".to_string(),
+            });
+        }
+    }
+    None
+}
+
+// Serialize buffer lines back into gemtext, fencing preformatted blocks.
+// The blank lines parse_gemtext pads headings with are dropped again.
+pub fn gemtext(lines: &[GemTextLine]) -> Vec<String> {
+    let is_heading = |i: usize| {
+        matches!(
+            lines.get(i),
+            Some(GemTextLine::H1(_)) | Some(GemTextLine::H2(_)) | Some(GemTextLine::H3(_))
+        )
+    };
+    let mut out = Vec::new();
+    let mut open_block = None;
+    for (i, line) in lines.iter().enumerate() {
+        if let GemTextLine::Line(text) = line {
+            if text.is_empty() && ((i > 0 && is_heading(i - 1)) || is_heading(i + 1)) {
+                continue;
+            }
+        }
+        let block = match line {
+            GemTextLine::Preformatted(block, _, _) => Some(*block),
+            _ => None,
+        };
+        if open_block.is_some() && open_block != block {
+            out.push("```".to_string());
+            open_block = None;
+        }
+        match line {
+            GemTextLine::H1(text) => out.push(format!("# {}", text)),
+            GemTextLine::H2(text) => out.push(format!("## {}", text)),
+            GemTextLine::H3(text) => out.push(format!("### {}", text)),
+            GemTextLine::Link(_, Some(label), url) => out.push(format!("=> {} {}", url, label)),
+            GemTextLine::Link(_, None, url) => out.push(format!("=> {}", url)),
+            GemTextLine::Line(text) => out.push(text.to_string()),
+            GemTextLine::Quote(text) => out.push(format!("> {}", text)),
+            GemTextLine::ListItem(text) => out.push(format!("* {}", text)),
+            GemTextLine::Preformatted(block, alt, text) => {
+                if open_block.is_none() {
+                    match alt {
+                        Some(alt) => out.push(format!("```{}", alt)),
+                        None => out.push("```".to_string()),
+                    }
+                    open_block = Some(*block);
+                }
+                out.push(text.to_string());
+            }
+        }
+    }
+    if open_block.is_some() {
+        out.push("```".to_string());
+    }
+    out
+}
+
+// Mark the byte ranges in spans within text. On a terminal the matches are
+// shown in reverse video, otherwise a row of carets is added beneath them.
+pub fn highlight(prefix: &str, text: &str, spans: &[(usize, usize)]) -> Vec<String> {
+    use std::io::IsTerminal;
+    if std::io::stdout().is_terminal() {
+        let mut row = prefix.to_string();
+        let mut last = 0;
+        for (start, end) in spans {
+            row.push_str(&text[last..*start]);
+            row.push_str(&format!("\x1b[7m{}\x1b[0m", &text[*start..*end]));
+            last = *end;
+        }
+        row.push_str(&text[last..]);
+        return vec![row];
+    }
+    let mut carets = " ".repeat(prefix.chars().count());
+    let mut last = 0;
+    for (start, end) in spans {
+        carets.push_str(&" ".repeat(text[last..*start].chars().count()));
+        carets.push_str(&"^".repeat(text[*start..*end].chars().count().max(1)));
+        last = *end;
+    }
+    vec![format!("{}{}", prefix, text), carets]
+}
+
+// The column width to wrap at: the configured width, or the terminal's.
+pub fn width(config: &config::Config) -> usize {
+    if config.wrap_width > 0 {
+        return config.wrap_width;
+    }
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(w), _)) if w > 0 => w as usize,
+        _ => 80,
+    }
+}
+
+// A unix timestamp as local date and time, for listings.
+pub fn format_time(time: i64) -> String {
+    use chrono::TimeZone;
+    match chrono::Local.timestamp_opt(time, 0) {
+        chrono::LocalResult::Single(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+// Greedily fill rows of at most width columns. Words longer than a row are
+// left whole on a row of their own.
+fn wrap(text: &str, width: usize, first_prefix: &str, rest_prefix: &str) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = first_prefix.to_string();
+    let mut row_len = first_prefix.chars().count();
+    let mut row_empty = true;
+    for word in text.split_whitespace() {
+        let word_len = word.chars().count();
+        if !row_empty && row_len + 1 + word_len > width {
+            rows.push(row);
+            row = rest_prefix.to_string();
+            row_len = rest_prefix.chars().count();
+            row_empty = true;
+        }
+        if !row_empty {
+            row.push(' ');
+            row_len += 1;
+        }
+        row.push_str(word);
+        row_len += word_len;
+        row_empty = false;
+    }
+    rows.push(row.trim_end().to_string());
+    rows
+}
@@ -0,0 +1,122 @@
+use crate::*;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Saved browsing sessions.
+// Each session is a file under <config dir>/rei/sessions holding the current
+// url, cursor line and back/forward history as "key value" lines. The session
+// named "default" is written on quit and restored on start when save_session
+// is set in the config.
+pub const DEFAULT: &str = "default";
+
+pub fn save(name: &str, buf: &PageBuf, hist: &History) -> ReiResult<()> {
+    let path = session_path(name)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::fs("Unable to create", dir, e))?;
+    }
+    let mut out = String::new();
+    if let Some(url) = &buf.url {
+        out.push_str(&format!("url {}\n", url));
+        out.push_str(&format!("line {}\n", buf.curr_line));
+    }
+    out.push_str(&format!("current {}\n", hist.curr_entry));
+    for url in &hist.entry {
+        out.push_str(&format!("history {}\n", url));
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .map_err(|e| Error::fs("Unable to open", &path, e))?;
+    file.write_all(out.as_bytes())
+        .map_err(|e| Error::fs("Unable to write to", &path, e))
+}
+
+// Replace the history with a saved session's and reload its page.
+pub async fn restore(
+    name: &str,
+    buf: &mut PageBuf,
+    hist: &mut History,
+    config: &config::Config,
+) -> ReiResult<()> {
+    let path = session_path(name)?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .open(&path)
+        .map_err(|e| Error::fs("Unable to open", &path, e))?;
+    let mut data = String::new();
+    file.read_to_string(&mut data)
+        .map_err(|e| Error::fs("Unable to read", &path, e))?;
+
+    let mut url = None;
+    let mut line = 0;
+    let mut entry = Vec::new();
+    let mut curr_entry = 0;
+    for l in data.lines() {
+        let mut split = l.splitn(2, ' ');
+        match (split.next(), split.next()) {
+            (Some("url"), Some(value)) => url = url::Url::parse(value).ok(),
+            (Some("line"), Some(value)) => line = value.parse().unwrap_or(0),
+            (Some("current"), Some(value)) => curr_entry = value.parse().unwrap_or(0),
+            (Some("history"), Some(value)) => {
+                if let Ok(url) = url::Url::parse(value) {
+                    entry.push(url);
+                }
+            }
+            _ => {}
+        }
+    }
+    hist.entry = entry;
+    hist.curr_entry = curr_entry.min(hist.entry.len().saturating_sub(1));
+    if let Some(url) = url {
+        exec::visit(&url, buf, hist, false, config).await?;
+        buf.curr_line = line.min(buf.lines.len().saturating_sub(1));
+    }
+    Ok(())
+}
+
+pub fn exists(name: &str) -> bool {
+    session_path(name)
+        .map(|path| path.exists())
+        .unwrap_or(false)
+}
+
+// Print the name of every saved session.
+pub fn list() {
+    let mut names = Vec::new();
+    if let Ok(dir) = sessions_dir() {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+}
+
+fn session_path(name: &str) -> ReiResult<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::command(
+            "Session names may only use letters, numbers, - and _.",
+        ));
+    }
+    Ok(sessions_dir()?.join(name))
+}
+
+fn sessions_dir() -> ReiResult<PathBuf> {
+    let mut path = dirs::config_dir().ok_or(Error::NoConfigDir)?;
+    path.push("rei");
+    path.push("sessions");
+    Ok(path)
+}
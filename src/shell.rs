@@ -0,0 +1,53 @@
+use crate::*;
+use std::process::{Command, Stdio};
+
+/// Running shell commands.
+// Commands are run with sh -c and share the terminal unless their input or
+// output is connected to the buffer. ed's "!" is printed when each finishes.
+pub fn run(command: &str) -> ReiResult<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .status()
+        .map_err(|e| Error::shell(command, e))?;
+    report(status);
+    Ok(())
+}
+
+// Run a command with data as its standard input.
+pub fn pipe(command: &str, data: &[u8]) -> ReiResult<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::shell(command, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that exits without reading all of its input is not an error.
+        let _ = stdin.write_all(data);
+    }
+    let status = child.wait().map_err(|e| Error::shell(command, e))?;
+    report(status);
+    Ok(())
+}
+
+// Run a command and collect its standard output.
+pub fn read(command: &str) -> ReiResult<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::shell(command, e))?;
+    report(output.status);
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn report(status: std::process::ExitStatus) {
+    match status.code() {
+        Some(0) => {}
+        Some(code) => println!("Exit status {}", code),
+        None => println!("Terminated by signal"),
+    }
+    println!("!");
+}
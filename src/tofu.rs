@@ -0,0 +1,218 @@
+use crate::fetch::ServerCert;
+use crate::*;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Trust on first use certificate pinning.
+// Pins live in <config dir>/rei/known_hosts, one "host port fingerprint expiry"
+// entry per line. An expiry that could not be read is stored as "-".
+pub struct KnownHost {
+    host: String,
+    port: u16,
+    fingerprint: String,
+    not_after: Option<i64>,
+}
+
+pub struct KnownHosts {
+    hosts: Vec<KnownHost>,
+}
+
+/// What the user decided to do about a changed certificate.
+pub enum CertChoice {
+    Accept,
+    AcceptOnce,
+    Reject,
+}
+
+/// How the certificate of a fetched page came to be trusted.
+#[derive(Clone, Copy)]
+pub enum Trust {
+    Pinned,   // Matched the pinned certificate.
+    FirstUse, // Pinned on first use.
+    Renewed,  // Replaced a pin that had expired.
+    Changed,  // Replaced a pin at the user's request.
+    Once,     // Accepted for a single request without pinning.
+}
+
+impl Trust {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Trust::Pinned => "matches pinned certificate",
+            Trust::FirstUse => "pinned on first use",
+            Trust::Renewed => "pinned after the old pin expired",
+            Trust::Changed => "pinned after a certificate change",
+            Trust::Once => "accepted once, not pinned",
+        }
+    }
+}
+
+enum TrustState {
+    Trusted,
+    Unknown,
+    Expired(usize), // Index of the old pin.
+    Changed(usize),
+}
+
+impl KnownHosts {
+    pub fn load() -> KnownHosts {
+        let mut hosts = Vec::new();
+        if let Some(path) = known_hosts_path() {
+            if let Ok(mut file) = OpenOptions::new().read(true).open(path) {
+                let mut buf = String::new();
+                if file.read_to_string(&mut buf).is_err() {
+                    return KnownHosts { hosts };
+                }
+                for line in buf.lines() {
+                    let mut split = line.split_whitespace();
+                    if let (Some(host), Some(port), Some(fingerprint), Some(not_after)) =
+                        (split.next(), split.next(), split.next(), split.next())
+                    {
+                        // Older stores wrote an unknown expiry as 0.
+                        let not_after = match not_after {
+                            "-" | "0" => Ok(None),
+                            not_after => not_after.parse::<i64>().map(Some),
+                        };
+                        if let (Ok(port), Ok(not_after)) = (port.parse::<u16>(), not_after) {
+                            hosts.push(KnownHost {
+                                host: host.to_string(),
+                                port,
+                                fingerprint: fingerprint.to_string(),
+                                not_after,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        KnownHosts { hosts }
+    }
+
+    pub fn save(&self) -> ReiResult<()> {
+        let path = known_hosts_path().ok_or(Error::NoConfigDir)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::fs("Unable to create", dir, e))?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| Error::fs("Unable to open", &path, e))?;
+        for h in &self.hosts {
+            let not_after = match h.not_after {
+                Some(not_after) => not_after.to_string(),
+                None => "-".to_string(),
+            };
+            file.write_fmt(format_args!(
+                "{} {} {} {}\n",
+                h.host, h.port, h.fingerprint, not_after
+            ))
+            .map_err(|e| Error::fs("Unable to write to", &path, e))?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, host: &str, port: u16, cert: &ServerCert) -> TrustState {
+        match self
+            .hosts
+            .iter()
+            .position(|h| h.host == host && h.port == port)
+        {
+            None => TrustState::Unknown,
+            Some(i) if self.hosts[i].fingerprint == cert.fingerprint => TrustState::Trusted,
+            // A pin whose expiry is unknown never counts as expired.
+            Some(i)
+                if self.hosts[i]
+                    .not_after
+                    .is_some_and(|t| t < chrono::Utc::now().timestamp()) =>
+            {
+                TrustState::Expired(i)
+            }
+            Some(i) => TrustState::Changed(i),
+        }
+    }
+
+    fn pin(&mut self, host: &str, port: u16, cert: &ServerCert) {
+        self.hosts.retain(|h| !(h.host == host && h.port == port));
+        self.hosts.push(KnownHost {
+            host: host.to_string(),
+            port,
+            fingerprint: cert.fingerprint.clone(),
+            not_after: cert.not_after,
+        });
+    }
+}
+
+// Check a server certificate against the store, pinning it on first use and
+// asking the user what to do if it has changed, even when the old pin has
+// expired. If the pin can not be saved the certificate is trusted just once.
+pub fn verify(url: &url::Url, cert: &ServerCert) -> ReiResult<Trust> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| Error::command("Missing host in url."))?;
+    let port = url.port().unwrap_or(1965);
+    let mut known = KnownHosts::load();
+    let state = known.check(host, port, cert);
+    let trust = match state {
+        TrustState::Trusted => return Ok(Trust::Pinned),
+        TrustState::Unknown => Trust::FirstUse,
+        TrustState::Expired(i) | TrustState::Changed(i) => {
+            let old = &known.hosts[i];
+            let expired = matches!(state, TrustState::Expired(_));
+            if expired {
+                println!(
+                    "The pinned certificate for {}:{} has expired and the server presented a new one.",
+                    host, port
+                );
+            } else {
+                println!(
+                    "WARNING: The certificate for {}:{} has changed!",
+                    host, port
+                );
+            }
+            println!(
+                "Pinned:    {} (expires {})",
+                old.fingerprint,
+                format_expiry(old.not_after)
+            );
+            println!(
+                "Presented: {} (expires {})",
+                cert.fingerprint,
+                format_expiry(cert.not_after)
+            );
+            match interface::prompt_cert_choice() {
+                CertChoice::Accept if expired => Trust::Renewed,
+                CertChoice::Accept => Trust::Changed,
+                CertChoice::AcceptOnce => return Ok(Trust::Once),
+                CertChoice::Reject => {
+                    return Err(Error::Tls {
+                        context: format!("Certificate for {}:{} rejected", host, port),
+                        source: None,
+                    })
+                }
+            }
+        }
+    };
+    known.pin(host, port, cert);
+    if let Err(e) = known.save() {
+        println!("Warning: {}", e);
+        return Ok(Trust::Once);
+    }
+    Ok(trust)
+}
+
+pub fn format_expiry(not_after: Option<i64>) -> String {
+    use chrono::TimeZone;
+    match not_after.map(|t| chrono::Utc.timestamp_opt(t, 0)) {
+        Some(chrono::LocalResult::Single(time)) => time.format("%Y-%m-%d").to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn known_hosts_path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("rei");
+    path.push("known_hosts");
+    Some(path)
+}